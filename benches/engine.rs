@@ -0,0 +1,274 @@
+//! Compares the tape-based engine against the previous `Rc<RefCell>` graph,
+//! which is reproduced in the `legacy` module below.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ketting::{Mlp, Value};
+
+mod legacy {
+    use std::{
+        cell::RefCell,
+        collections::{HashSet, VecDeque},
+        ops::{Add, Mul, Neg, Sub},
+        rc::Rc,
+    };
+
+    use uuid::Uuid;
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Operation {
+        Add,
+        Mul,
+        Tanh,
+        Pow,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Value {
+        id: Uuid,
+        data: Rc<RefCell<f32>>,
+        grad: Rc<RefCell<f32>>,
+        children: Vec<Self>,
+        op: Option<Operation>,
+    }
+
+    impl Value {
+        pub fn new(data: f32) -> Self {
+            Self {
+                id: Uuid::new_v4(),
+                data: Rc::new(RefCell::new(data)),
+                grad: Rc::new(RefCell::new(0.0)),
+                children: Vec::new(),
+                op: None,
+            }
+        }
+
+        pub fn borrow_data(&self) -> f32 {
+            *self.data.borrow()
+        }
+
+        fn update_grad(&self, grad: f32) {
+            *self.grad.borrow_mut() += grad;
+        }
+
+        pub fn tanh(&self) -> Self {
+            let mut v = Value::new(self.borrow_data().tanh());
+            v.children.push(self.clone());
+            v.op = Some(Operation::Tanh);
+            v
+        }
+
+        pub fn pow(&self, rhs: i32) -> Self {
+            let mut v = Value::new(self.borrow_data().powi(rhs));
+            v.children.push(self.clone());
+            v.children.push(Value::new(rhs as f32));
+            v.op = Some(Operation::Pow);
+            v
+        }
+
+        fn backward_local(&self) {
+            let grad = *self.grad.borrow();
+            match self.op {
+                Some(Operation::Add) => {
+                    self.children[0].update_grad(grad);
+                    self.children[1].update_grad(grad);
+                }
+                Some(Operation::Mul) => {
+                    self.children[0].update_grad(self.children[1].borrow_data() * grad);
+                    self.children[1].update_grad(self.children[0].borrow_data() * grad);
+                }
+                Some(Operation::Tanh) => {
+                    self.children[0].update_grad((1.0 - self.borrow_data().powi(2)) * grad);
+                }
+                Some(Operation::Pow) => {
+                    let x = self.children[0].borrow_data();
+                    let n = self.children[1].borrow_data();
+                    self.children[0].update_grad(n * x.powf(n - 1.0) * grad);
+                }
+                None => {}
+            }
+        }
+
+        pub fn backward(&self) {
+            *self.grad.borrow_mut() = 1.0;
+            for v in build_topo(self).iter().rev() {
+                v.backward_local();
+            }
+        }
+    }
+
+    impl Add for Value {
+        type Output = Self;
+        fn add(self, rhs: Self) -> Self::Output {
+            let mut v = Self::new(self.borrow_data() + rhs.borrow_data());
+            v.children.push(self);
+            v.children.push(rhs);
+            v.op = Some(Operation::Add);
+            v
+        }
+    }
+
+    impl Mul for Value {
+        type Output = Self;
+        fn mul(self, rhs: Self) -> Self::Output {
+            let mut v = Self::new(self.borrow_data() * rhs.borrow_data());
+            v.children.push(self);
+            v.children.push(rhs);
+            v.op = Some(Operation::Mul);
+            v
+        }
+    }
+
+    impl Neg for Value {
+        type Output = Self;
+        fn neg(self) -> Self::Output {
+            self * Value::new(-1.0)
+        }
+    }
+
+    impl Sub for Value {
+        type Output = Self;
+        fn sub(self, rhs: Self) -> Self::Output {
+            self + (-rhs)
+        }
+    }
+
+    fn build_topo(v: &Value) -> Vec<&Value> {
+        let mut collected: HashSet<Uuid> = HashSet::new();
+        let mut queue: VecDeque<&Value> = VecDeque::new();
+        queue.push_front(v);
+        let mut topo = Vec::new();
+
+        while let Some(value) = queue.pop_front() {
+            let mut can_collect = true;
+            for child in value.children.iter() {
+                if !collected.contains(&child.id) {
+                    queue.push_front(value);
+                    queue.push_front(child);
+                    can_collect = false;
+                    break;
+                }
+            }
+
+            if can_collect {
+                collected.insert(value.id);
+                topo.push(value);
+            }
+        }
+        topo
+    }
+
+    /// The 3-4-4-1 MLP from `examples/basic.rs`, as nested weight vectors.
+    pub struct Mlp {
+        layers: Vec<Vec<(Vec<Value>, Value)>>,
+    }
+
+    impl Mlp {
+        pub fn new(nin: usize, nouts: &[usize]) -> Self {
+            let mut sizes = vec![nin];
+            sizes.extend(nouts);
+            let layers = sizes
+                .windows(2)
+                .map(|w| {
+                    (0..w[1])
+                        .map(|j| {
+                            let ws = (0..w[0]).map(|i| Value::new(0.1 * (i + j) as f32));
+                            (ws.collect(), Value::new(0.1))
+                        })
+                        .collect()
+                })
+                .collect();
+            Self { layers }
+        }
+
+        pub fn forward(&self, x: &[Value]) -> Vec<Value> {
+            let mut out: Vec<Value> = x.into();
+            for layer in self.layers.iter() {
+                out = layer
+                    .iter()
+                    .map(|(w, b)| x_dot_w(&out, w, b.clone()).tanh())
+                    .collect();
+            }
+            out
+        }
+    }
+
+    fn x_dot_w(x: &[Value], w: &[Value], b: Value) -> Value {
+        x.iter()
+            .zip(w.iter())
+            .fold(b, |acc, (xi, wi)| acc + xi.clone() * wi.clone())
+    }
+}
+
+const XS: [[f32; 3]; 4] = [
+    [2.0, 3.0, -1.0],
+    [3.0, -1.0, 0.5],
+    [1.5, 1.0, 1.0],
+    [1.0, 1.0, -1.0],
+];
+const YS: [f32; 4] = [1.0, -1.0, -1.0, 1.0];
+
+fn mlp_step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mlp_step");
+
+    let m: Mlp = Mlp::new(3, &[4, 4, 1]);
+    group.bench_function("tape", |b| {
+        b.iter(|| {
+            let loss: Value = XS
+                .iter()
+                .zip(YS)
+                .map(|(x, y)| {
+                    let x: Vec<Value> = x.iter().map(|&xi| Value::new(xi)).collect();
+                    (y - &m.forward(&x)[0]).pow(2)
+                })
+                .sum();
+            loss.backward();
+            black_box(loss.borrow_data())
+        })
+    });
+
+    let m = legacy::Mlp::new(3, &[4, 4, 1]);
+    group.bench_function("legacy", |b| {
+        b.iter(|| {
+            let loss = XS
+                .iter()
+                .zip(YS)
+                .map(|(x, y)| {
+                    let x: Vec<_> = x.iter().map(|&xi| legacy::Value::new(xi)).collect();
+                    (legacy::Value::new(y) - m.forward(&x)[0].clone()).pow(2)
+                })
+                .fold(legacy::Value::new(0.0), |a, b| a + b);
+            loss.backward();
+            black_box(loss.borrow_data())
+        })
+    });
+
+    group.finish();
+}
+
+fn chain_backward(c: &mut Criterion) {
+    const LEN: usize = 1_000;
+    let mut group = c.benchmark_group("chain_backward");
+
+    group.bench_function("tape", |b| {
+        b.iter(|| {
+            let x = Value::new(0.5);
+            let out = (0..LEN).fold(x.clone(), |acc, _| (&acc * &x).tanh());
+            out.backward();
+            black_box(x.borrow_grad())
+        })
+    });
+
+    group.bench_function("legacy", |b| {
+        b.iter(|| {
+            let x = legacy::Value::new(0.5);
+            let out = (0..LEN).fold(x.clone(), |acc, _| (acc * x.clone()).tanh());
+            out.backward();
+            black_box(out.borrow_data())
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, mlp_step, chain_backward);
+criterion_main!(benches);
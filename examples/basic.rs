@@ -1,15 +1,15 @@
 use ketting::{Mlp, Value};
 
 fn main() {
-    let m = Mlp::new(3, &[4, 4, 1]);
+    let m: Mlp = Mlp::new(3, &[4, 4, 1]);
 
-    let xs = vec![
+    let xs = [
         vec![Value::new(2.0), Value::new(3.0), Value::new(-1.0)],
         vec![Value::new(3.0), Value::new(-1.0), Value::new(0.5)],
         vec![Value::new(1.5), Value::new(1.0), Value::new(1.0)],
         vec![Value::new(1.0), Value::new(1.0), Value::new(-1.0)],
     ];
-    let ys = vec![
+    let ys = [
         Value::new(1.0),
         Value::new(-1.0),
         Value::new(-1.0),
@@ -21,9 +21,9 @@ fn main() {
         let res: Vec<Value> = ys
             .iter()
             .zip(ypreds.iter())
-            .map(|(y, pred)| (y.clone() - pred[0].clone()).pow(2))
+            .map(|(y, pred)| (y - &pred[0]).pow(2))
             .collect();
-        let loss = res.iter().fold(Value::new(0.0), |a, b| a + b.clone());
+        let loss: Value = res.iter().sum();
         println!("{} loss: {}", k, loss);
         for p in m.parameters() {
             p.set_grad(0.0);
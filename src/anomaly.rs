@@ -0,0 +1,195 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+use crate::{Float, Operation, Value};
+
+thread_local! {
+    static ANOMALY_ENABLED: Cell<bool> = const { Cell::new(false) };
+    pub(crate) static ANOMALY: RefCell<Option<AnomalyError>> = const { RefCell::new(None) };
+}
+
+#[cfg(test)]
+thread_local! {
+    /// How many anomaly panics the hook has kept quiet on this thread.
+    pub(crate) static SILENCED: Cell<usize> = const { Cell::new(0) };
+}
+
+static SILENCE_HOOK: Once = Once::new();
+
+/// Where a [`detect_anomaly`] check fired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    /// The op's output was not finite.
+    Forward,
+    /// The gradient `backward` was about to pass to the operand at this
+    /// position was not finite.
+    Backward { operand: usize },
+}
+
+/// An operand of the op that produced a non-finite value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Operand {
+    pub data: f64,
+    pub label: Option<String>,
+}
+
+/// The first non-finite value seen by [`detect_anomaly`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnomalyError {
+    pub pass: Pass,
+    /// The op, as drawn by [`Value::to_dot`], e.g. `ln` or `+`.
+    pub op: String,
+    pub operands: Vec<Operand>,
+    /// The offending output or gradient.
+    pub value: f64,
+    /// Label of the op's output node. Always `None` for a forward anomaly,
+    /// which is caught before the node can be labelled.
+    pub label: Option<String>,
+}
+
+impl fmt::Display for AnomalyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pass {
+            Pass::Forward => write!(f, "`{}` produced {}", self.op, self.value)?,
+            Pass::Backward { operand } => write!(
+                f,
+                "`{}` produced gradient {} for operand {}",
+                self.op, self.value, operand
+            )?,
+        }
+        if let Some(label) = &self.label {
+            write!(f, " at `{}`", label)?;
+        }
+        write!(f, " from operands [")?;
+        for (i, x) in self.operands.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", x.data)?;
+            if let Some(label) = &x.label {
+                write!(f, " (`{}`)", label)?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+impl std::error::Error for AnomalyError {}
+
+/// Runs `f` with anomaly detection on for the current thread, stopping at the
+/// first op whose output, or whose gradient for one of its operands in
+/// `backward`, is NaN or infinite.
+///
+/// The checks cost a little on every op, so they are off by default. On an
+/// anomaly `f` is unwound with a panic, which is caught here and turned into
+/// the returned error; any other panic is passed on. The panic hook is
+/// silenced for anomalies, so they print nothing; the first call wraps the
+/// hook installed at that point, and a hook set afterwards replaces the
+/// wrapper.
+///
+/// Unwinding stops `f` midway, but a `backward` that was interrupted puts
+/// every `grad` cell of its graph back as it found it. Built with
+/// `panic = "abort"`, an anomaly aborts the process instead.
+///
+/// ```
+/// let x = ketting::Value::new(-1.0);
+/// let err = ketting::detect_anomaly(|| x.ln()).unwrap_err();
+/// assert_eq!(err.op, "ln");
+/// assert_eq!(err.operands[0].data, -1.0);
+/// ```
+pub fn detect_anomaly<R>(f: impl FnOnce() -> R) -> Result<R, AnomalyError> {
+    SILENCE_HOOK.call_once(silence_anomalies);
+    let prev = ANOMALY_ENABLED.with(|enabled| enabled.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    ANOMALY_ENABLED.with(|enabled| enabled.set(prev));
+    // always taken, even if `f` caught the panic itself, since the hook would
+    // otherwise keep silencing this thread's panics
+    let anomaly = ANOMALY.with(|anomaly| anomaly.borrow_mut().take());
+    match (result, anomaly) {
+        (Ok(r), _) => Ok(r),
+        (Err(_), Some(err)) => Err(err),
+        (Err(payload), None) => panic::resume_unwind(payload),
+    }
+}
+
+/// Wraps the current panic hook so that it skips the panics `raise` throws,
+/// which are always pending in `ANOMALY` on the panicking thread. The wrapper
+/// is installed once and left in place, since restoring the previous hook
+/// would race with other threads setting their own.
+fn silence_anomalies() {
+    let prev = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let pending = ANOMALY
+            .try_with(|anomaly| anomaly.try_borrow().is_ok_and(|a| a.is_some()))
+            .unwrap_or(false);
+        if pending {
+            #[cfg(test)]
+            SILENCED.with(|n| n.set(n.get() + 1));
+        } else {
+            prev(info);
+        }
+    }));
+}
+
+fn is_anomaly_enabled() -> bool {
+    ANOMALY_ENABLED.with(|enabled| enabled.get())
+}
+
+fn raise<T: Float>(
+    pass: Pass,
+    op: &Operation<T>,
+    children: &[Value<T>],
+    value: T,
+    label: Option<String>,
+) -> ! {
+    let err = AnomalyError {
+        pass,
+        op: op.to_string(),
+        operands: children
+            .iter()
+            .map(|c| Operand {
+                data: c.borrow_data().to_f64().unwrap_or(f64::NAN),
+                label: c.label(),
+            })
+            .collect(),
+        value: value.to_f64().unwrap_or(f64::NAN),
+        label,
+    };
+    let msg = format!("anomaly detected: {}", err);
+    ANOMALY.with(|anomaly| *anomaly.borrow_mut() = Some(err));
+    panic!("{}", msg)
+}
+
+/// Checks the output of `op` on the operands `children` builds, if anomaly
+/// detection is on. The operands are only built to report an anomaly.
+pub(crate) fn check_forward<T: Float>(
+    op: Option<&Operation<T>>,
+    children: impl FnOnce() -> Vec<Value<T>>,
+    out: T,
+) {
+    if let Some(op) = op {
+        if !out.is_finite() && is_anomaly_enabled() {
+            raise(Pass::Forward, op, &children(), out, None);
+        }
+    }
+}
+
+/// Checks the gradient `node` passes to its operand at `index` among
+/// `children`, if anomaly detection is on.
+pub(crate) fn check_backward<T: Float>(
+    node: &Value<T>,
+    children: &[Value<T>],
+    index: usize,
+    grad: T,
+) {
+    if let Some(op) = &node.op {
+        if !grad.is_finite() && is_anomaly_enabled() {
+            let pass = Pass::Backward { operand: index };
+            raise(pass, op, children, grad, node.label());
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+
+use crate::{shared::MaybeSync, shared::Shared, Float, Operation, Value};
+
+/// A user-defined op with its own backward rule, recorded in the graph by
+/// [`Value::apply`].
+///
+/// ```
+/// use ketting::{CustomOp, Value};
+///
+/// /// `x` clipped to `[-1, 1]`, passing the gradient straight through.
+/// #[derive(Debug)]
+/// struct SoftClip;
+///
+/// impl CustomOp for SoftClip {
+///     fn forward(&self, inputs: &[f32]) -> f32 {
+///         inputs[0].clamp(-1.0, 1.0)
+///     }
+///
+///     fn backward(&self, _inputs: &[f32], _out: f32, grad: f32) -> Vec<f32> {
+///         vec![grad]
+///     }
+/// }
+///
+/// let x = Value::new(3.0);
+/// let y = Value::apply(SoftClip, &[x.clone()]);
+/// y.backward();
+/// assert_eq!(y.borrow_data(), 1.0);
+/// assert_eq!(x.borrow_grad(), 1.0);
+/// ```
+pub trait CustomOp<T: Float = f32>: fmt::Debug + MaybeSync {
+    /// Computes the output from the operands' data.
+    fn forward(&self, inputs: &[T]) -> T;
+
+    /// Given the operands' data, the output `out` and the gradient `grad`
+    /// arriving at the output, returns the gradient to pass to each operand,
+    /// in order.
+    ///
+    /// [`jvp`](crate::jvp) and [`Value::grad_graph`] call this with a `grad`
+    /// of one to get the local derivatives, so for those to be meaningful the
+    /// result should be linear in `grad`. `grad_graph` treats the local
+    /// derivatives as constants, so derivatives of order two and up do not
+    /// see through a custom op.
+    fn backward(&self, inputs: &[T], out: T, grad: T) -> Vec<T>;
+}
+
+/// A shared custom op, compared by identity so that `Operation` stays
+/// `PartialEq`.
+#[derive(Clone)]
+pub(crate) struct CustomFn<T: Float>(Shared<dyn CustomOp<T>>);
+
+impl<T: Float> CustomFn<T> {
+    /// Calls the op's backward rule and checks it returned one gradient per
+    /// operand.
+    pub(crate) fn backward(&self, children: &[Value<T>], out: T, grad: T) -> Vec<T> {
+        let inputs: Vec<T> = children.iter().map(|c| c.borrow_data()).collect();
+        let grads = self.0.backward(&inputs, out, grad);
+        assert_eq!(
+            grads.len(),
+            inputs.len(),
+            "{:?}::backward returned {} gradients for {} inputs",
+            self.0,
+            grads.len(),
+            inputs.len()
+        );
+        grads
+    }
+}
+
+impl<T: Float> fmt::Debug for CustomFn<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T: Float> PartialEq for CustomFn<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T: Float> Value<T> {
+    /// Applies a [`CustomOp`] to `inputs`, recording it in the graph so that
+    /// `backward` calls the op's own backward rule.
+    pub fn apply(op: impl CustomOp<T> + 'static, inputs: &[Value<T>]) -> Self {
+        let data: Vec<T> = inputs.iter().map(|x| x.borrow_data()).collect();
+        let out = op.forward(&data);
+        let op: Shared<dyn CustomOp<T>> = Shared::new(op);
+        Value::from_op(out, Operation::Custom(CustomFn(op)), || inputs.to_vec())
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt::{self, Write};
+
+use crate::{build_topo, Float, Operation, Value};
+
+impl<T: Float> fmt::Display for Operation<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Add => write!(f, "+"),
+            Operation::Mul => write!(f, "*"),
+            Operation::Tanh => write!(f, "tanh"),
+            Operation::Exp => write!(f, "exp"),
+            Operation::Pow => write!(f, "pow"),
+            Operation::Relu => write!(f, "relu"),
+            Operation::LeakyRelu(alpha) => write!(f, "leaky_relu({})", alpha),
+            Operation::Sigmoid => write!(f, "sigmoid"),
+            Operation::Softplus => write!(f, "softplus"),
+            Operation::Ln => write!(f, "ln"),
+            Operation::Log(base) => write!(f, "log({})", base),
+            Operation::Sqrt => write!(f, "sqrt"),
+            Operation::Abs => write!(f, "abs"),
+            Operation::Sin => write!(f, "sin"),
+            Operation::Cos => write!(f, "cos"),
+            Operation::Tan => write!(f, "tan"),
+            Operation::Sinh => write!(f, "sinh"),
+            Operation::Cosh => write!(f, "cosh"),
+            Operation::Atan2 => write!(f, "atan2"),
+            Operation::Max => write!(f, "max"),
+            Operation::Min => write!(f, "min"),
+            Operation::Clamp(lo, hi) => write!(f, "clamp({}, {})", lo, hi),
+            Operation::Where(cond) => write!(f, "where({})", cond),
+            Operation::StopGradient => write!(f, "stop_gradient"),
+            Operation::Custom(op) => write!(f, "{:?}", op),
+        }
+    }
+}
+
+/// Escapes a string for use inside a quoted DOT label. Record labels also
+/// give `{`, `}`, `|`, `<` and `>` a meaning, so those are escaped too.
+fn escape(s: &str, record: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        let special = match c {
+            '"' | '\\' => true,
+            '{' | '}' | '|' | '<' | '>' => record,
+            _ => false,
+        };
+        if special {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+impl<T: Float> Value<T> {
+    /// Renders the graph that `self` depends on in Graphviz DOT format, in the
+    /// style of micrograd's `draw_dot`.
+    ///
+    /// Each node is a record showing its label, data and grad, fed by a small
+    /// node naming the op that produced it. A node shared by several parts of
+    /// the graph is drawn once. Render with e.g. `dot -Tsvg graph.dot`.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph {\n    rankdir=LR;\n");
+        for v in build_topo(self) {
+            let label = v.label().unwrap_or_default();
+            writeln!(
+                dot,
+                "    n{} [shape=record, label=\"{{ {} | data {:.4} | grad {:.4} }}\"];",
+                v.id,
+                escape(&label, true),
+                v.borrow_data(),
+                v.borrow_grad()
+            )
+            .unwrap();
+            if let Some(op) = &v.op {
+                let op = escape(&op.to_string(), false);
+                writeln!(dot, "    n{}op [label=\"{}\"];", v.id, op).unwrap();
+                writeln!(dot, "    n{}op -> n{};", v.id, v.id).unwrap();
+                for child in v.children.borrow().iter() {
+                    writeln!(dot, "    n{} -> n{}op;", child.id, v.id).unwrap();
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
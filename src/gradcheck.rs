@@ -0,0 +1,93 @@
+use std::fmt;
+
+use crate::{no_grad, Float, Value};
+
+/// One input whose gradient from [`Value::backward`] disagrees with the
+/// central finite difference.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch<T> {
+    /// Position of the input in the slice passed to [`gradcheck`].
+    pub index: usize,
+    pub analytic: T,
+    pub numeric: T,
+}
+
+/// The inputs that failed a [`gradcheck`], in input order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GradCheckError<T> {
+    pub mismatches: Vec<Mismatch<T>>,
+}
+
+impl<T: Float> fmt::Display for GradCheckError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "gradient check failed for")?;
+        for m in self.mismatches.iter() {
+            write!(
+                f,
+                " input {} (analytic {}, numeric {})",
+                m.index, m.analytic, m.numeric
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Float> std::error::Error for GradCheckError<T> {}
+
+/// Compares the gradients `backward` computes for `f` against central finite
+/// differences.
+///
+/// `f` builds the graph from the leaf `inputs` and is called once for the
+/// analytic gradient, then twice more per input, with that input's data
+/// nudged by `+eps` and `-eps`. An input passes when
+/// `|analytic - numeric| <= tol * max(1, |numeric|)`, i.e. `tol` is an
+/// absolute tolerance for small gradients and a relative one for large.
+///
+/// Hooks registered with [`Value::register_hook`] are not run, since the
+/// finite differences cannot see them. The data and grad cells of `inputs`
+/// are left as they were found.
+pub fn gradcheck<T, F>(f: F, inputs: &[Value<T>], eps: T, tol: T) -> Result<(), GradCheckError<T>>
+where
+    T: Float,
+    F: Fn(&[Value<T>]) -> Value<T>,
+{
+    let saved: Vec<T> = inputs.iter().map(|x| x.borrow_grad()).collect();
+    for x in inputs {
+        x.set_grad(T::zero());
+    }
+    f(inputs).sweep(T::one(), true, false);
+    let analytic: Vec<T> = inputs.iter().map(|x| x.borrow_grad()).collect();
+    for (x, grad) in inputs.iter().zip(saved) {
+        x.set_grad(grad);
+    }
+
+    let _guard = no_grad();
+    let two = T::one() + T::one();
+    let mismatches: Vec<Mismatch<T>> = inputs
+        .iter()
+        .zip(analytic)
+        .enumerate()
+        .filter_map(|(index, (x, analytic))| {
+            let x0 = x.borrow_data();
+            *x.borrow_data_mut() = x0 + eps;
+            let plus = f(inputs).borrow_data();
+            *x.borrow_data_mut() = x0 - eps;
+            let minus = f(inputs).borrow_data();
+            *x.borrow_data_mut() = x0;
+
+            let numeric = (plus - minus) / (two * eps);
+            let ok = (analytic - numeric).abs() <= tol * numeric.abs().max(T::one());
+            (!ok).then_some(Mismatch {
+                index,
+                analytic,
+                numeric,
+            })
+        })
+        .collect();
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(GradCheckError { mismatches })
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt;
+
+use crate::{shared::Lock, shared::MaybeSync, Float, Value};
+
+/// A gradient hook, see [`Value::register_hook`].
+trait Hook<T>: Fn(T) -> T + MaybeSync {}
+
+impl<T, F: Fn(T) -> T + MaybeSync> Hook<T> for F {}
+
+/// The hooks registered on a node, in registration order.
+pub(crate) struct Hooks<T>(Lock<Vec<Box<dyn Hook<T>>>>);
+
+impl<T: Float> Hooks<T> {
+    /// Passes the gradient in `grad` through every hook in turn.
+    pub(crate) fn apply(&self, grad: &Lock<T>) {
+        let hooks = self.0.borrow();
+        if !hooks.is_empty() {
+            let g = *grad.borrow();
+            *grad.borrow_mut() = hooks.iter().fold(g, |g, hook| hook(g));
+        }
+    }
+}
+
+impl<T> Default for Hooks<T> {
+    fn default() -> Self {
+        Self(Lock::new(Vec::new()))
+    }
+}
+
+impl<T> fmt::Debug for Hooks<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hooks({})", self.0.borrow().len())
+    }
+}
+
+impl<T: Float> Value<T> {
+    /// Registers `hook` to be called by [`Value::backward`] once this node's
+    /// gradient for the current sweep is final, before it is passed on to its
+    /// children. Whatever the hook returns replaces that gradient, both in
+    /// what is propagated and in what is added to the `grad` cell; return the
+    /// argument unchanged to only observe it.
+    ///
+    /// Hooks run in registration order, each on the previous one's result.
+    /// They only see the gradient arriving in this sweep, never what a leaf
+    /// accumulated in earlier ones. [`jacobian`](crate::jacobian) and
+    /// [`gradcheck`](crate::gradcheck) do not run hooks.
+    ///
+    /// ```
+    /// let x = ketting::Value::new(2.0);
+    /// let y = x.tanh();
+    /// // gradient reversal
+    /// y.register_hook(|g| -g);
+    /// (&y * 3.0).backward();
+    /// assert_eq!(y.borrow_grad(), -3.0);
+    /// assert!(x.borrow_grad() < 0.0);
+    /// ```
+    pub fn register_hook(&self, hook: impl Fn(T) -> T + MaybeSync + 'static) {
+        self.hooks.0.borrow_mut().push(Box::new(hook));
+    }
+}
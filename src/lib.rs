@@ -1,308 +1,1367 @@
 use std::{
-    cell::{RefCell, RefMut},
-    collections::{HashSet, VecDeque},
+    cell::Cell,
+    collections::HashMap,
     fmt,
-    iter::Sum,
-    ops::{Add, Div, Mul, Neg, Sub},
-    rc::Rc,
+    hash::{Hash, Hasher},
+    iter::{Product, Sum},
+    marker::PhantomData,
+    ops::{Add, AddAssign, Deref, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use rand::Rng;
-use uuid::Uuid;
 
+mod anomaly;
+mod custom;
+mod dot;
+mod gradcheck;
+mod hook;
+mod shared;
 #[cfg(test)]
 mod tests;
+
+pub use anomaly::{detect_anomaly, AnomalyError, Operand, Pass};
+use custom::CustomFn;
+pub use custom::CustomOp;
+pub use gradcheck::{gradcheck, GradCheckError, Mismatch};
+use hook::Hooks;
+
+#[cfg(not(feature = "sync"))]
+use shared::Mark;
+use shared::{Lock, MaybeSync, Ref, RefMut, Shared};
+
+/// The scalar type held by a [`Value`].
+///
+/// Implemented for every [`num_traits::Float`] that can be printed, so `f32`,
+/// `f64` and custom float types all work. `f32` is the default throughout.
+/// With the `sync` feature the type must also be `Send + Sync`.
+pub trait Float: num_traits::Float + fmt::Debug + fmt::Display + MaybeSync + 'static {}
+
+impl<T: num_traits::Float + fmt::Debug + fmt::Display + MaybeSync + 'static> Float for T {}
+
+/// Converts a constant into `T`. Only used for small literals, which every
+/// float type can represent.
+fn lit<T: Float>(x: f64) -> T {
+    T::from(x).unwrap()
+}
+
 #[derive(Clone, Debug, PartialEq)]
-enum Operation {
+enum Operation<T: Float> {
     Add,
     Mul,
     Tanh,
     Exp,
     Pow,
+    Relu,
+    LeakyRelu(T),
+    Sigmoid,
+    Softplus,
+    Ln,
+    Log(T),
+    Sqrt,
+    Abs,
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Atan2,
+    Max,
+    Min,
+    Clamp(T, T),
+    Where(bool),
+    StopGradient,
+    Custom(CustomFn<T>),
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct Value {
-    id: Uuid,
-    pub data: Rc<RefCell<f32>>,
-    pub grad: Rc<RefCell<f32>>,
-    pub children: Vec<Self>,
-    op: Option<Operation>,
-    label: Option<String>,
+/// Logistic function, evaluated so that `exp` is only ever taken of a
+/// non-positive number and cannot overflow.
+fn sigmoid<T: Float>(x: T) -> T {
+    if x >= T::zero() {
+        T::one() / (T::one() + (-x).exp())
+    } else {
+        let e = x.exp();
+        e / (T::one() + e)
+    }
 }
 
-impl Value {
-    pub fn new(data: f32) -> Self {
-        Self {
-            id: Uuid::new_v4(),
-            data: Rc::new(RefCell::new(data)),
-            grad: Rc::new(RefCell::new(0.0)),
-            children: Vec::new(),
-            op: None,
-            label: None,
+/// Source of node ids, which give every node a cheap identity for `Eq`,
+/// `Hash` and finding it on a tape.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Source of traversal epochs, used to mark nodes as visited without hashing.
+#[cfg(not(feature = "sync"))]
+static NEXT_EPOCH: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    static GRAD_ENABLED: Cell<bool> = const { Cell::new(true) };
+}
+
+/// Returns whether ops on this thread currently record the graph.
+pub fn is_grad_enabled() -> bool {
+    GRAD_ENABLED.with(|enabled| enabled.get())
+}
+
+/// Stops recording the graph on the current thread until the returned guard
+/// is dropped.
+///
+/// While the guard is alive every op still computes its data, but the result
+/// is a leaf: it keeps no children and no `op`, so intermediate values are
+/// freed as soon as they are no longer used and `backward` has nothing to
+/// propagate through. Guards nest, and each restores the previous state.
+///
+/// ```
+/// let a = ketting::Value::new(2.0);
+/// let b = {
+///     let _guard = ketting::no_grad();
+///     a.tanh()
+/// };
+/// assert!(b.children().is_empty());
+/// ```
+pub fn no_grad() -> NoGradGuard {
+    NoGradGuard {
+        prev: GRAD_ENABLED.with(|enabled| enabled.replace(false)),
+        _not_send: PhantomData,
+    }
+}
+
+/// Guard returned by [`no_grad`].
+#[must_use = "graph recording resumes as soon as the guard is dropped"]
+pub struct NoGradGuard {
+    prev: bool,
+    /// The flag is thread-local, so the guard must be dropped on the thread
+    /// that created it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for NoGradGuard {
+    fn drop(&mut self) {
+        GRAD_ENABLED.with(|enabled| enabled.set(self.prev));
+    }
+}
+
+/// A single entry on the tape: the result of an op together with the operands
+/// it was computed from.
+#[derive(Debug)]
+pub struct Node<T: Float = f32> {
+    id: usize,
+    pub data: Shared<Lock<T>>,
+    pub grad: Lock<T>,
+    children: Lock<Vec<Value<T>>>,
+    op: Option<Operation<T>>,
+    /// Set when `backward_with` dropped `children` to free the graph.
+    released: Lock<bool>,
+    label: Lock<Option<String>>,
+    hooks: Hooks<T>,
+    /// The epoch of the last traversal that visited this node.
+    #[cfg(not(feature = "sync"))]
+    visited: Mark,
+}
+
+impl<T: Float> Drop for Node<T> {
+    /// Unlinks the graph below this node one level at a time. The default
+    /// drop would recurse once per node of a chain and overflow the stack on a
+    /// deep graph.
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(self.children.get_mut());
+        while let Some(mut child) = stack.pop() {
+            // only nodes that nothing else holds are freed now, and those are
+            // emptied first so that they drop without recursing
+            if let Some(node) = Shared::get_mut(&mut child.0) {
+                stack.append(node.children.get_mut());
+            }
+        }
+    }
+}
+
+/// A cheap, clonable handle to a [`Node`] in the computation graph.
+///
+/// Equality and hashing are by identity: two handles are equal when they
+/// point at the same node, whatever their data. Use [`Value::approx_eq`] to
+/// compare the numbers.
+#[derive(Clone, Debug)]
+pub struct Value<T: Float = f32>(Shared<Node<T>>);
+
+impl<T: Float> PartialEq for Value<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T: Float> Eq for Value<T> {}
+
+impl<T: Float> Hash for Value<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T: Float> Deref for Value<T> {
+    type Target = Node<T>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: Float> Value<T> {
+    pub fn new(data: T) -> Self {
+        Self::from_op(data, None, Vec::new)
+    }
+
+    /// Records `data` as the result of `op` on the operands `children`
+    /// builds. The operands are only built when the graph is recorded, so
+    /// under [`no_grad`] an op allocates nothing beyond its own node.
+    fn from_op(
+        data: T,
+        op: impl Into<Option<Operation<T>>>,
+        children: impl FnOnce() -> Vec<Value<T>>,
+    ) -> Self {
+        let op = op.into();
+        let data_cell = Shared::new(Lock::new(data));
+        if !is_grad_enabled() {
+            anomaly::check_forward(op.as_ref(), children, data);
+            return Self::from_parts(data_cell, None, Vec::new());
         }
+        let children = children();
+        anomaly::check_forward(op.as_ref(), || children.clone(), data);
+        Self::from_parts(data_cell, op, children)
+    }
+
+    fn from_parts(
+        data: Shared<Lock<T>>,
+        op: Option<Operation<T>>,
+        children: Vec<Value<T>>,
+    ) -> Self {
+        Self(Shared::new(Node {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            data,
+            grad: Lock::new(T::zero()),
+            children: Lock::new(children),
+            op,
+            released: Lock::new(false),
+            label: Lock::new(None),
+            hooks: Hooks::default(),
+            #[cfg(not(feature = "sync"))]
+            visited: Mark::default(),
+        }))
     }
-    pub fn borrow_data(&self) -> f32 {
+
+    pub fn borrow_data(&self) -> T {
         *self.data.borrow()
     }
-    pub fn borrow_data_mut(&self) -> RefMut<f32> {
+    pub fn borrow_data_mut(&self) -> RefMut<'_, T> {
         self.data.borrow_mut()
     }
-    pub fn borrow_grad(&self) -> f32 {
+    pub fn borrow_grad(&self) -> T {
         *self.grad.borrow()
     }
-    pub fn borrow_grad_mut(&self) -> f32 {
+    pub fn borrow_grad_mut(&self) -> T {
         *self.grad.borrow_mut()
     }
-    pub fn set_grad(&self, grad: f32) {
+    pub fn set_grad(&self, grad: T) {
         *self.grad.borrow_mut() = grad;
     }
-    pub fn update_grad(&self, grad: f32) {
-        *self.grad.borrow_mut() += grad;
+    pub fn update_grad(&self, grad: T) {
+        let mut g = self.grad.borrow_mut();
+        *g = *g + grad;
+    }
+
+    /// Whether the data of `self` and `other` differ by at most `tol`.
+    pub fn approx_eq(&self, other: &Self, tol: T) -> bool {
+        (self.borrow_data() - other.borrow_data()).abs() <= tol
+    }
+
+    /// The name shown by `Display` and [`Value::to_dot`], if one was set.
+    pub fn label(&self) -> Option<String> {
+        self.label.borrow().clone()
+    }
+    pub fn set_label(&self, label: impl Into<String>) {
+        *self.label.borrow_mut() = Some(label.into());
+    }
+    /// Builder form of [`Value::set_label`], e.g.
+    /// `let h = (&x * &w).with_label("h")`.
+    pub fn with_label(self, label: impl Into<String>) -> Self {
+        self.set_label(label);
+        self
+    }
+
+    /// Returns a new leaf that shares this value's data cell but not its graph
+    /// or gradient. Updates to the data through either handle are visible
+    /// through both, but nothing is ever propagated from the detached value
+    /// back into the graph.
+    pub fn detach(&self) -> Self {
+        Self::from_parts(self.data.clone(), None, Vec::new())
+    }
+
+    /// Identity in the forward pass, but blocks the gradient: `backward` leaves
+    /// everything below this node untouched. Unlike [`Value::detach`] the node
+    /// stays in the graph, recording what it was computed from.
+    pub fn stop_gradient(&self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n, Operation::StopGradient, || vec![self.clone()])
     }
 
     pub fn tanh(&self) -> Self {
         let n = self.borrow_data();
-        let tanh = ((2.0 * n).exp() - 1.0) / ((2.0 * n).exp() + 1.0);
-        let mut v = Value::new(tanh);
-        v.children.push(self.clone());
-        v.op = Some(Operation::Tanh);
-        v
+        // the textbook (e^2n - 1) / (e^2n + 1) is inf/inf = NaN for n > ~45
+        Value::from_op(n.tanh(), Operation::Tanh, || vec![self.clone()])
+    }
+
+    pub fn sin(&self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n.sin(), Operation::Sin, || vec![self.clone()])
+    }
+
+    pub fn cos(&self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n.cos(), Operation::Cos, || vec![self.clone()])
+    }
+
+    pub fn tan(&self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n.tan(), Operation::Tan, || vec![self.clone()])
+    }
+
+    pub fn sinh(&self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n.sinh(), Operation::Sinh, || vec![self.clone()])
+    }
+
+    pub fn cosh(&self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n.cosh(), Operation::Cosh, || vec![self.clone()])
+    }
+
+    /// Four-quadrant arctangent of `self / x`, as in `f32::atan2`.
+    ///
+    /// Both partial derivatives are undefined at the origin and are taken to
+    /// be `0` there.
+    pub fn atan2(&self, x: &Self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n.atan2(x.borrow_data()), Operation::Atan2, || {
+            vec![self.clone(), x.clone()]
+        })
+    }
+
+    /// The larger of `self` and `other`. The gradient goes to the larger
+    /// operand; on a tie it is split evenly between the two. `NaN` in either
+    /// operand gives `NaN`, unlike `f32::max`, and so does the gradient of
+    /// both.
+    pub fn max(&self, other: &Self) -> Self {
+        let (a, b) = (self.borrow_data(), other.borrow_data());
+        let n = if a.is_nan() || b.is_nan() {
+            T::nan()
+        } else {
+            a.max(b)
+        };
+        Value::from_op(n, Operation::Max, || vec![self.clone(), other.clone()])
+    }
+
+    /// The smaller of `self` and `other`. The gradient goes to the smaller
+    /// operand; on a tie it is split evenly between the two. `NaN` in either
+    /// operand gives `NaN`, unlike `f32::min`, and so does the gradient of
+    /// both.
+    pub fn min(&self, other: &Self) -> Self {
+        let (a, b) = (self.borrow_data(), other.borrow_data());
+        let n = if a.is_nan() || b.is_nan() {
+            T::nan()
+        } else {
+            a.min(b)
+        };
+        Value::from_op(n, Operation::Min, || vec![self.clone(), other.clone()])
+    }
+
+    /// Restricts `self` to `[lo, hi]`. The gradient passes through unchanged
+    /// for `lo <= self <= hi`, bounds included, and is zero outside. As with
+    /// `f32::clamp`, `NaN` stays `NaN`.
+    ///
+    /// Panics if `lo > hi`.
+    pub fn clamp(&self, lo: T, hi: T) -> Self {
+        assert!(lo <= hi, "clamp with lo > hi");
+        let x = self.borrow_data();
+        let n = if x < lo {
+            lo
+        } else if x > hi {
+            hi
+        } else {
+            x
+        };
+        Value::from_op(n, Operation::Clamp(lo, hi), || vec![self.clone()])
+    }
+
+    /// `a` if `cond` holds and `b` otherwise. Both are recorded as operands,
+    /// but the gradient only goes to the selected one.
+    pub fn where_(cond: bool, a: &Self, b: &Self) -> Self {
+        let n = if cond {
+            a.borrow_data()
+        } else {
+            b.borrow_data()
+        };
+        Value::from_op(n, Operation::Where(cond), || vec![a.clone(), b.clone()])
     }
 
     pub fn exp(&self) -> Self {
         let n = self.borrow_data();
-        let mut v = Value::new(n.exp());
-        v.children.push(self.clone());
-        v.op = Some(Operation::Exp);
-        v
+        Value::from_op(n.exp(), Operation::Exp, || vec![self.clone()])
+    }
+
+    pub fn relu(&self) -> Self {
+        let n = self.borrow_data();
+        // not `n.max(0)`, which would turn NaN into 0
+        let out = if n <= T::zero() { T::zero() } else { n };
+        Value::from_op(out, Operation::Relu, || vec![self.clone()])
+    }
+
+    /// Like [`Value::relu`], but negative inputs are scaled by `alpha` instead of zeroed.
+    pub fn leaky_relu(&self, alpha: T) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(
+            if n > T::zero() { n } else { alpha * n },
+            Operation::LeakyRelu(alpha),
+            || vec![self.clone()],
+        )
+    }
+
+    pub fn sigmoid(&self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(sigmoid(n), Operation::Sigmoid, || vec![self.clone()])
+    }
+
+    /// `ln(1 + e^x)`, a smooth approximation of [`Value::relu`].
+    pub fn softplus(&self) -> Self {
+        let n = self.borrow_data();
+        // rewritten as max(x, 0) + ln(1 + e^-|x|) so e^x never overflows
+        Value::from_op(
+            n.max(T::zero()) + (-n.abs()).exp().ln_1p(),
+            Operation::Softplus,
+            || vec![self.clone()],
+        )
+    }
+
+    /// Natural logarithm.
+    ///
+    /// Follows IEEE semantics at the edge of the domain: `ln(0)` is `-inf` with
+    /// a gradient of `+inf`. A negative input gives a `NaN` value, but the
+    /// gradient is still the finite `1 / x`.
+    pub fn ln(&self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n.ln(), Operation::Ln, || vec![self.clone()])
     }
 
+    /// Logarithm with respect to an arbitrary (constant) `base`.
+    ///
+    /// Has the same edge behaviour as [`Value::ln`].
+    pub fn log(&self, base: T) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n.log(base), Operation::Log(base), || vec![self.clone()])
+    }
+
+    /// Square root.
+    ///
+    /// `sqrt(0)` is `0` with a gradient of `+inf`; negative inputs produce `NaN`.
+    pub fn sqrt(&self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n.sqrt(), Operation::Sqrt, || vec![self.clone()])
+    }
+
+    /// Absolute value. The subgradient at `0` is taken to be `0`.
+    pub fn abs(&self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n.abs(), Operation::Abs, || vec![self.clone()])
+    }
+
+    /// Raises to a constant integer power. The exponent is recorded as a leaf
+    /// so that the node has the same shape as one built by [`Value::powf`].
     pub fn pow(&self, rhs: i32) -> Self {
         let n = self.borrow_data();
-        // WARNING: Not sure treating it as Value is right
-        // Karpathy left it as a 'scalar'
-        let rhs_val = Value::new(rhs as f32);
-        let mut v = Value::new(n.powi(rhs));
-        v.children.push(self.clone());
-        v.children.push(rhs_val);
-        v.op = Some(Operation::Pow);
-        v
+        Value::from_op(n.powi(rhs), Operation::Pow, || {
+            vec![self.clone(), Value::new(T::from(rhs).unwrap())]
+        })
     }
 
-    fn backward_local(&self) {
-        if let Some(operation) = &self.op {
-            match operation {
-                Operation::Add => {
-                    let new_grad = 1.0 * self.borrow_grad();
-                    self.children[0].update_grad(new_grad);
-                    self.children[1].update_grad(new_grad);
+    /// Raises to a real power, where the exponent is itself part of the graph.
+    ///
+    /// The forward pass follows `f32::powf`, so a negative base with a
+    /// non-integer exponent gives `NaN`. The gradient with respect to the
+    /// exponent, `x^n * ln(x)`, is only defined for a positive base; for a zero
+    /// or negative base it is taken to be `0` (the limit as `x -> 0+` for
+    /// `n > 0`).
+    pub fn powf(&self, rhs: &Self) -> Self {
+        let n = self.borrow_data();
+        Value::from_op(n.powf(rhs.borrow_data()), Operation::Pow, || {
+            vec![self.clone(), rhs.clone()]
+        })
+    }
+
+    /// Partial derivative of this node's data with respect to each child's,
+    /// evaluated at the current data. Shared by the reverse sweep in
+    /// `backward_local` and the forward sweep in [`jvp`].
+    fn local_derivatives(&self) -> Vec<T> {
+        let Some(operation) = &self.op else {
+            return Vec::new();
+        };
+        let children = self.operands();
+        let x = || children[0].borrow_data();
+        let out = self.borrow_data();
+        match operation {
+            Operation::Add => vec![T::one(); children.len()],
+            Operation::Mul => {
+                // each child gets the product of all the others, built from
+                // prefix and suffix products so a zero factor is handled
+                let data: Vec<T> = children.iter().map(|c| c.borrow_data()).collect();
+                let mut suffix = vec![T::one(); data.len() + 1];
+                for i in (0..data.len()).rev() {
+                    suffix[i] = suffix[i + 1] * data[i];
                 }
-                Operation::Mul => {
-                    let new_grad = self.children[1].borrow_data() * self.borrow_grad();
-                    self.children[0].update_grad(new_grad);
-                    let new_grad = self.children[0].borrow_data() * self.borrow_grad();
-                    self.children[1].update_grad(new_grad);
+                let mut prefix = T::one();
+                let mut partials = Vec::with_capacity(data.len());
+                for i in 0..data.len() {
+                    partials.push(prefix * suffix[i + 1]);
+                    prefix = prefix * data[i];
                 }
-                Operation::Tanh => {
-                    let new_grad = (1.0 - self.borrow_data().powi(2)) * self.borrow_grad();
-                    self.children[0].update_grad(new_grad);
+                partials
+            }
+            Operation::Tanh => vec![T::one() - out.powi(2)],
+            Operation::Exp => vec![out],
+            Operation::Pow => {
+                let (x, n) = (x(), children[1].borrow_data());
+                // x^n -> n*x^(n-1), except that x^0 is constant even at x = 0,
+                // where this would be 0 * inf
+                let dx = if n == T::zero() && x == T::zero() {
+                    T::zero()
+                } else {
+                    n * x.powf(n - T::one())
+                };
+                // x^n -> x^n*ln(x), see `Value::powf` for x <= 0
+                let dn = if x > T::zero() {
+                    out * x.ln()
+                } else {
+                    T::zero()
+                };
+                vec![dx, dn]
+            }
+            Operation::Relu => {
+                // the subgradient at 0 is taken to be 0
+                vec![if out > T::zero() { T::one() } else { T::zero() }]
+            }
+            Operation::LeakyRelu(alpha) => vec![if x() > T::zero() { T::one() } else { *alpha }],
+            Operation::Sigmoid => vec![out * (T::one() - out)],
+            // d/dx ln(1 + e^x) = sigmoid(x)
+            Operation::Softplus => vec![sigmoid(x())],
+            Operation::Ln => vec![x().recip()],
+            Operation::Log(base) => vec![(x() * base.ln()).recip()],
+            Operation::Sqrt => vec![(lit::<T>(2.0) * out).recip()],
+            Operation::Abs => {
+                // signum(0.0) is 1.0, so zero is handled explicitly
+                let x = x();
+                vec![if x == T::zero() {
+                    T::zero()
+                } else {
+                    x.signum()
+                }]
+            }
+            Operation::Sin => vec![x().cos()],
+            Operation::Cos => vec![-x().sin()],
+            Operation::Tan => vec![T::one() + out.powi(2)],
+            Operation::Sinh => vec![x().cosh()],
+            Operation::Cosh => vec![x().sinh()],
+            Operation::Atan2 => {
+                // atan2(y, x) -> (x, -y) / (x^2 + y^2)
+                let (y, x) = (x(), children[1].borrow_data());
+                let r2 = x * x + y * y;
+                if r2 > T::zero() {
+                    vec![x / r2, -y / r2]
+                } else {
+                    vec![T::zero(), T::zero()]
                 }
-                Operation::Exp => {
-                    let new_grad = self.borrow_data() * self.borrow_grad();
-                    self.children[0].update_grad(new_grad);
+            }
+            Operation::Max | Operation::Min => {
+                let (a, b) = (x(), children[1].borrow_data());
+                let half = lit::<T>(0.5);
+                // whether the first operand was selected, barring a tie
+                let first = if operation == &Operation::Max {
+                    a > b
+                } else {
+                    a < b
+                };
+                if a.is_nan() || b.is_nan() {
+                    vec![T::nan(), T::nan()]
+                } else if a == b {
+                    vec![half, half]
+                } else if first {
+                    vec![T::one(), T::zero()]
+                } else {
+                    vec![T::zero(), T::one()]
                 }
-                Operation::Pow => {
-                    // x^n -> n*x^(n-1)
-                    let new_grad = self.children[1].borrow_data()
-                        * self.children[0]
-                            .borrow_data()
-                            .powf(self.children[1].borrow_data() - 1.0)
-                        * self.borrow_grad();
-                    self.children[0].update_grad(new_grad);
-                    // WARNING: nothing happening to children[1]
+            }
+            Operation::Clamp(lo, hi) => {
+                let x = x();
+                vec![if *lo <= x && x <= *hi {
+                    T::one()
+                } else {
+                    T::zero()
+                }]
+            }
+            Operation::Where(cond) => {
+                if *cond {
+                    vec![T::one(), T::zero()]
+                } else {
+                    vec![T::zero(), T::one()]
                 }
             }
+            Operation::StopGradient => vec![T::zero()],
+            Operation::Custom(op) => op.backward(&children, out, T::one()),
         }
     }
 
+    fn backward_local(&self) {
+        // a zero gradient contributes nothing, and skipping it keeps an
+        // infinite local derivative (e.g. exp of a large input) from turning
+        // 0 * inf into NaN
+        let grad = self.borrow_grad();
+        if grad == T::zero() || self.op.is_none() {
+            return;
+        }
+        let grads: Vec<Option<T>> = match &self.op {
+            Some(Operation::Custom(op)) => {
+                let grads = op.backward(&self.operands(), self.borrow_data(), grad);
+                grads.into_iter().map(Some).collect()
+            }
+            // an operand with a zero local derivative (a blocked or unselected
+            // branch) is skipped, so that an infinite grad cannot reach it as
+            // 0 * inf = NaN
+            _ => self
+                .local_derivatives()
+                .into_iter()
+                .map(|local| (local != T::zero()).then(|| local * grad))
+                .collect(),
+        };
+        let children = self.children.borrow();
+        for (i, (child, g)) in children.iter().zip(grads).enumerate() {
+            let Some(g) = g else {
+                continue;
+            };
+            anomaly::check_backward(self, &children, i, g);
+            child.update_grad(g);
+        }
+    }
+
+    /// The children of an op node, or a panic if `backward_with` has already
+    /// released them.
+    fn operands(&self) -> Ref<'_, Vec<Value<T>>> {
+        assert!(
+            !*self.released.borrow(),
+            "backward through a graph that has already been released; \
+             pass `retain_graph: true` to `backward_with` to keep it"
+        );
+        self.children.borrow()
+    }
+
+    /// The operands this node was computed from. Empty for a leaf, and for an
+    /// op node whose graph was released by [`Value::backward_with`].
+    pub fn children(&self) -> Vec<Value<T>> {
+        self.children.borrow().clone()
+    }
+
+    /// Propagates gradients from `self` to every node it depends on, as if
+    /// `self` were a loss with gradient one. Same as
+    /// `self.backward_with(T::one(), true)`.
     pub fn backward(&self) {
-        self.set_grad(1.0);
+        self.backward_with(T::one(), true);
+    }
+
+    /// Propagates the gradient `seed` from `self` to every node it depends on.
+    ///
+    /// As in PyTorch, only leaves accumulate: their `grad` cells are added
+    /// to, so calling this twice without resetting them in between counts
+    /// their gradients twice. The cells of op nodes, `self` included, are
+    /// reset at the start and end up holding only this call's gradient. With
+    /// `retain_graph` false, every op node on the way drops its children once
+    /// the sweep is done, so that intermediate values no longer referenced
+    /// elsewhere are freed; backpropagating through such a node again panics.
+    pub fn backward_with(&self, seed: T, retain_graph: bool) {
+        self.sweep(seed, retain_graph, true);
+    }
+
+    /// [`Value::backward_with`], optionally skipping the hooks. Code that
+    /// measures the graph's own derivatives, like [`jacobian`] and
+    /// [`gradcheck`], leaves them out.
+    pub(crate) fn sweep(&self, seed: T, retain_graph: bool, run_hooks: bool) {
+        let topo = build_topo(self);
+        // while the sweep runs every cell holds only this sweep's gradient, so
+        // that hooks see what arrives now; leaves get their old one back after
+        let saved: Vec<T> = topo
+            .iter()
+            .map(|v| std::mem::replace(&mut *v.grad.borrow_mut(), T::zero()))
+            .collect();
+        let restore = RestoreGrads { topo: &topo, saved };
+        self.update_grad(seed);
 
-        let topo = build_topo(&self);
         for v in topo.iter().rev() {
+            // every node that uses `v` comes later on the tape, so its grad is final
+            if run_hooks {
+                v.hooks.apply(&v.grad);
+            }
             v.backward_local();
         }
+        drop(restore);
+        if !retain_graph {
+            for v in topo.iter().filter(|v| v.op.is_some()) {
+                *v.released.borrow_mut() = true;
+                drop(std::mem::take(&mut *v.children.borrow_mut()));
+            }
+        }
+    }
+
+    /// The same local derivatives as `backward_local`, but built out of ops on
+    /// `grad` and the operands, so that the result is itself differentiable.
+    /// `None` stands for a contribution that is identically zero.
+    fn backward_graph_local(&self, grad: &Value<T>) -> Vec<Option<Value<T>>> {
+        let Some(operation) = &self.op else {
+            return Vec::new();
+        };
+        let c = self.operands();
+        let scale = |k: T| Some(grad * k);
+        match operation {
+            Operation::Add => c.iter().map(|_| Some(grad.clone())).collect(),
+            Operation::Mul => (0..c.len())
+                .map(|i| {
+                    let others = c.iter().enumerate().filter(|&(j, _)| j != i);
+                    Some(grad * others.map(|(_, v)| v).product::<Value<T>>())
+                })
+                .collect(),
+            Operation::Tanh => vec![Some(grad * (-self.pow(2) + T::one()))],
+            Operation::Exp => vec![Some(grad * self)],
+            Operation::Pow => {
+                let (x, n) = (&c[0], &c[1]);
+                // only 0^0 needs the zero, see `local_derivatives`; elsewhere
+                // dx must stay a function of n for the mixed partials
+                let at_origin = n.borrow_data() == T::zero() && x.borrow_data() == T::zero();
+                let dx = (!at_origin).then(|| grad * n * x.powf(&(n - T::one())));
+                let dn = (x.borrow_data() > T::zero()).then(|| grad * self * x.ln());
+                vec![dx, dn]
+            }
+            Operation::Relu => {
+                let active = self.borrow_data() > T::zero();
+                vec![active.then(|| grad.clone())]
+            }
+            Operation::LeakyRelu(alpha) => {
+                if c[0].borrow_data() > T::zero() {
+                    vec![Some(grad.clone())]
+                } else {
+                    vec![scale(*alpha)]
+                }
+            }
+            Operation::Sigmoid => vec![Some(grad * self * (-self + T::one()))],
+            Operation::Softplus => vec![Some(grad * c[0].sigmoid())],
+            Operation::Ln => vec![Some(grad / &c[0])],
+            Operation::Log(base) => vec![Some(grad / (&c[0] * base.ln()))],
+            Operation::Sqrt => vec![Some(grad / (self * lit::<T>(2.0)))],
+            Operation::Abs => {
+                let x = c[0].borrow_data();
+                vec![(x != T::zero()).then(|| grad * x.signum())]
+            }
+            Operation::Sin => vec![Some(grad * c[0].cos())],
+            Operation::Cos => vec![Some(-(grad * c[0].sin()))],
+            Operation::Tan => vec![Some(grad * (self.pow(2) + T::one()))],
+            Operation::Sinh => vec![Some(grad * c[0].cosh())],
+            Operation::Cosh => vec![Some(grad * c[0].sinh())],
+            Operation::Atan2 => {
+                let (y, x) = (&c[0], &c[1]);
+                let r2 = x * x + y * y;
+                if r2.borrow_data() > T::zero() {
+                    vec![Some(grad * x / &r2), Some(-(grad * y) / &r2)]
+                } else {
+                    vec![None, None]
+                }
+            }
+            Operation::Max | Operation::Min => {
+                let (a, b) = (c[0].borrow_data(), c[1].borrow_data());
+                let first = if operation == &Operation::Max {
+                    a > b
+                } else {
+                    a < b
+                };
+                if a.is_nan() || b.is_nan() {
+                    vec![scale(T::nan()), scale(T::nan())]
+                } else if a == b {
+                    vec![scale(lit(0.5)), scale(lit(0.5))]
+                } else if first {
+                    vec![Some(grad.clone()), None]
+                } else {
+                    vec![None, Some(grad.clone())]
+                }
+            }
+            Operation::Clamp(lo, hi) => {
+                let x = c[0].borrow_data();
+                vec![(*lo <= x && x <= *hi).then(|| grad.clone())]
+            }
+            Operation::Where(cond) => {
+                if *cond {
+                    vec![Some(grad.clone()), None]
+                } else {
+                    vec![None, Some(grad.clone())]
+                }
+            }
+            Operation::StopGradient => vec![None],
+            // the local derivatives enter as constants; see `CustomOp::backward`
+            Operation::Custom(op) => op
+                .backward(&c, self.borrow_data(), T::one())
+                .into_iter()
+                .map(scale)
+                .collect(),
+        }
+    }
+
+    /// Returns the gradient of `self` with respect to each of `wrt`, like
+    /// [`Value::backward`] would, but as new nodes in the graph rather than
+    /// plain numbers.
+    ///
+    /// Because the gradients are ordinary `Value`s they can be differentiated
+    /// again, which gives second and higher derivatives. Nothing is written
+    /// to any `grad` cell. An input that `self` doesn't depend on gets a
+    /// constant zero.
+    pub fn grad_graph(&self, wrt: &[&Value<T>]) -> Vec<Value<T>> {
+        let topo = build_topo(self);
+        let positions = tape_positions(&topo);
+        let position = |v: &Value<T>| positions.get(&v.id).copied();
+
+        let mut grads: Vec<Option<Value<T>>> = vec![None; topo.len()];
+        grads[topo.len() - 1] = Some(Value::new(T::one()));
+        for (i, v) in topo.iter().enumerate().rev() {
+            let Some(grad) = grads[i].clone() else {
+                continue;
+            };
+            let locals = v.backward_graph_local(&grad);
+            for (child, local) in v.children.borrow().iter().zip(locals) {
+                let (Some(j), Some(local)) = (position(child), local) else {
+                    continue;
+                };
+                grads[j] = Some(match grads[j].take() {
+                    Some(acc) => acc + local,
+                    None => local,
+                });
+            }
+        }
+
+        wrt.iter()
+            .map(|v| {
+                position(v)
+                    .and_then(|i| grads[i].clone())
+                    .unwrap_or_else(|| Value::new(T::zero()))
+            })
+            .collect()
     }
 }
 
-impl fmt::Display for Value {
+impl<T: Float> fmt::Display for Value<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
             "Value(data={}, label={}, grad={})",
             self.borrow_data(),
-            self.label.clone().unwrap_or(String::new()),
+            self.label().unwrap_or_default(),
             self.borrow_grad()
         )
     }
 }
 
-impl Add for Value {
-    type Output = Self;
+impl<T: Float> Add for &Value<T> {
+    type Output = Value<T>;
     fn add(self, rhs: Self) -> Self::Output {
-        let mut v = Self::new(*self.data.borrow() + *rhs.data.borrow());
-        v.children.push(self.clone());
-        v.children.push(rhs.clone());
-        v.op = Some(Operation::Add);
-        v
+        Value::from_op(
+            self.borrow_data() + rhs.borrow_data(),
+            Operation::Add,
+            || vec![self.clone(), rhs.clone()],
+        )
     }
 }
 
-impl Add<f32> for Value {
-    type Output = Self;
-    fn add(self, rhs: f32) -> Self::Output {
-        self + Value::new(rhs)
+impl<T: Float> Add<T> for &Value<T> {
+    type Output = Value<T>;
+    fn add(self, rhs: T) -> Self::Output {
+        self + &Value::new(rhs)
     }
 }
 
-impl Add<Value> for f32 {
-    type Output = Value;
-    fn add(self, rhs: Value) -> Self::Output {
-        rhs + self
+impl<T: Float> Mul for &Value<T> {
+    type Output = Value<T>;
+    fn mul(self, rhs: Self) -> Self::Output {
+        Value::from_op(
+            self.borrow_data() * rhs.borrow_data(),
+            Operation::Mul,
+            || vec![self.clone(), rhs.clone()],
+        )
     }
 }
 
-impl Mul for Value {
-    type Output = Self;
-    fn mul(self, rhs: Self) -> Self::Output {
-        let mut v = Self::new(*self.data.borrow() * *rhs.data.borrow());
-        v.children.push(self.clone());
-        v.children.push(rhs.clone());
-        v.op = Some(Operation::Mul);
-        v
+impl<T: Float> Mul<T> for &Value<T> {
+    type Output = Value<T>;
+    fn mul(self, rhs: T) -> Self::Output {
+        self * &Value::new(rhs)
     }
 }
 
-impl Mul<f32> for Value {
-    type Output = Self;
-    fn mul(self, rhs: f32) -> Self::Output {
-        self * Value::new(rhs)
+impl<T: Float> Div for &Value<T> {
+    type Output = Value<T>;
+    fn div(self, rhs: Self) -> Self::Output {
+        self * &rhs.pow(-1)
     }
 }
 
-impl Div for Value {
-    type Output = Self;
-    fn div(self, rhs: Self) -> Self::Output {
-        self * rhs.pow(-1)
+impl<T: Float> Div<T> for &Value<T> {
+    type Output = Value<T>;
+    fn div(self, rhs: T) -> Self::Output {
+        self / &Value::new(rhs)
     }
 }
 
-impl Sub for Value {
-    type Output = Self;
+impl<T: Float> Sub for &Value<T> {
+    type Output = Value<T>;
     fn sub(self, rhs: Self) -> Self::Output {
-        self + (-rhs)
+        self + &(-rhs)
     }
 }
 
-impl Sub<f32> for Value {
-    type Output = Self;
-    fn sub(self, rhs: f32) -> Self::Output {
+impl<T: Float> Sub<T> for &Value<T> {
+    type Output = Value<T>;
+    fn sub(self, rhs: T) -> Self::Output {
         self + (-rhs)
     }
 }
 
-impl Neg for Value {
+impl<T: Float> Neg for &Value<T> {
+    type Output = Value<T>;
+    fn neg(self) -> Self::Output {
+        self * -T::one()
+    }
+}
+
+impl<T: Float> Neg for Value<T> {
     type Output = Self;
     fn neg(self) -> Self::Output {
-        self * -1.0
+        -&self
     }
 }
 
-// impl Sum for Value {
-//     fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
-//         let out = iter.next().unwrap();
-//         // let out = Value::new(0.0);
-//         iter.fold(out, |a, b| a + b)
-//     }
-// }
+/// Implements the owned and mixed-reference variants of a binary operator,
+/// plus its compound assignment, in terms of the `&Value` implementations.
+macro_rules! forward_binop {
+    ($trait:ident, $method:ident, $assign_trait:ident, $assign_method:ident) => {
+        impl<T: Float> $trait for Value<T> {
+            type Output = Value<T>;
+            fn $method(self, rhs: Value<T>) -> Self::Output {
+                (&self).$method(&rhs)
+            }
+        }
 
-fn build_topo(v: &Value) -> Vec<&Value> {
-    let mut collected: HashSet<Uuid> = HashSet::new();
-    let mut queue: VecDeque<&Value> = VecDeque::new();
-    queue.push_front(v);
-    let mut topo = Vec::new();
+        impl<T: Float> $trait<&Value<T>> for Value<T> {
+            type Output = Value<T>;
+            fn $method(self, rhs: &Value<T>) -> Self::Output {
+                (&self).$method(rhs)
+            }
+        }
+
+        impl<T: Float> $trait<Value<T>> for &Value<T> {
+            type Output = Value<T>;
+            fn $method(self, rhs: Value<T>) -> Self::Output {
+                self.$method(&rhs)
+            }
+        }
 
-    while let Some(value) = queue.pop_front() {
-        let mut can_collect = true;
-        for child in value.children.iter() {
-            if !collected.contains(&child.id) {
-                queue.push_front(value);
-                queue.push_front(child);
-                can_collect = false;
-                break;
+        impl<T: Float> $trait<T> for Value<T> {
+            type Output = Value<T>;
+            fn $method(self, rhs: T) -> Self::Output {
+                (&self).$method(rhs)
             }
         }
 
-        if can_collect {
-            collected.insert(value.id);
+        impl<T: Float> $assign_trait for Value<T> {
+            fn $assign_method(&mut self, rhs: Value<T>) {
+                *self = (&*self).$method(&rhs);
+            }
+        }
+
+        impl<T: Float> $assign_trait<&Value<T>> for Value<T> {
+            fn $assign_method(&mut self, rhs: &Value<T>) {
+                *self = (&*self).$method(rhs);
+            }
+        }
+
+        impl<T: Float> $assign_trait<T> for Value<T> {
+            fn $assign_method(&mut self, rhs: T) {
+                *self = (&*self).$method(rhs);
+            }
+        }
+    };
+}
+
+forward_binop!(Add, add, AddAssign, add_assign);
+forward_binop!(Sub, sub, SubAssign, sub_assign);
+forward_binop!(Mul, mul, MulAssign, mul_assign);
+forward_binop!(Div, div, DivAssign, div_assign);
+
+/// Implements the operators with a primitive float on the left-hand side.
+/// Coherence rules out doing this for a generic `T`, so custom float types
+/// only get the `Value op T` forms.
+macro_rules! scalar_lhs_ops {
+    ($t:ty) => {
+        impl Add<&Value<$t>> for $t {
+            type Output = Value<$t>;
+            fn add(self, rhs: &Value<$t>) -> Self::Output {
+                rhs + self
+            }
+        }
+
+        impl Mul<&Value<$t>> for $t {
+            type Output = Value<$t>;
+            fn mul(self, rhs: &Value<$t>) -> Self::Output {
+                rhs * self
+            }
+        }
+
+        impl Div<&Value<$t>> for $t {
+            type Output = Value<$t>;
+            fn div(self, rhs: &Value<$t>) -> Self::Output {
+                rhs.pow(-1) * self
+            }
+        }
+
+        impl Sub<&Value<$t>> for $t {
+            type Output = Value<$t>;
+            fn sub(self, rhs: &Value<$t>) -> Self::Output {
+                -rhs + self
+            }
+        }
+
+        impl Add<Value<$t>> for $t {
+            type Output = Value<$t>;
+            fn add(self, rhs: Value<$t>) -> Self::Output {
+                self + &rhs
+            }
+        }
+
+        impl Mul<Value<$t>> for $t {
+            type Output = Value<$t>;
+            fn mul(self, rhs: Value<$t>) -> Self::Output {
+                self * &rhs
+            }
+        }
+
+        impl Div<Value<$t>> for $t {
+            type Output = Value<$t>;
+            fn div(self, rhs: Value<$t>) -> Self::Output {
+                self / &rhs
+            }
+        }
+
+        impl Sub<Value<$t>> for $t {
+            type Output = Value<$t>;
+            fn sub(self, rhs: Value<$t>) -> Self::Output {
+                self - &rhs
+            }
+        }
+    };
+}
+
+scalar_lhs_ops!(f32);
+scalar_lhs_ops!(f64);
+
+/// Collects the terms of a sum or product into a single n-ary node, so that
+/// long reductions don't become deep chains of binary nodes. An empty
+/// iterator gives a leaf holding the identity, and a single term is returned
+/// as-is.
+fn reduce<T: Float>(iter: impl Iterator<Item = Value<T>>, op: Operation<T>) -> Value<T> {
+    let mut children: Vec<Value<T>> = iter.collect();
+    if children.len() == 1 {
+        return children.pop().unwrap();
+    }
+    let data = children.iter().map(|c| c.borrow_data());
+    let data = match op {
+        Operation::Mul => data.fold(T::one(), |a, b| a * b),
+        _ => data.fold(T::zero(), |a, b| a + b),
+    };
+    if children.is_empty() {
+        Value::new(data)
+    } else {
+        Value::from_op(data, op, move || children)
+    }
+}
+
+impl<T: Float> Sum for Value<T> {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        reduce(iter, Operation::Add)
+    }
+}
+
+impl<'a, T: Float> Sum<&'a Value<T>> for Value<T> {
+    fn sum<I: Iterator<Item = &'a Value<T>>>(iter: I) -> Self {
+        reduce(iter.cloned(), Operation::Add)
+    }
+}
+
+impl<T: Float> Product for Value<T> {
+    fn product<I: Iterator<Item = Self>>(iter: I) -> Self {
+        reduce(iter, Operation::Mul)
+    }
+}
+
+impl<'a, T: Float> Product<&'a Value<T>> for Value<T> {
+    fn product<I: Iterator<Item = &'a Value<T>>>(iter: I) -> Self {
+        reduce(iter.cloned(), Operation::Mul)
+    }
+}
+
+/// Builds the tape for `v`: every node it depends on, itself included, with
+/// each node after all of its children. Walking the tape backwards visits
+/// each node only after every node that uses it.
+fn build_topo<T: Float>(v: &Value<T>) -> Vec<Value<T>> {
+    build_tape(&[v])
+}
+
+/// Like [`build_topo`], but for the union of several roots' graphs.
+///
+/// An iterative post-order walk, so it takes time linear in the number of
+/// edges and a deep graph cannot overflow the stack.
+fn build_tape<T: Float>(roots: &[&Value<T>]) -> Vec<Value<T>> {
+    let mut visited = Visited::new();
+    // each entry is a node and whether its children have been pushed
+    let mut stack: Vec<(Value<T>, bool)> =
+        roots.iter().rev().map(|&r| (r.clone(), false)).collect();
+    let mut topo = Vec::new();
+
+    while let Some((value, expanded)) = stack.pop() {
+        if expanded {
             topo.push(value);
+            continue;
+        }
+        if !visited.insert(&value) {
+            continue;
+        }
+        stack.push((value.clone(), true));
+        // reversed, so that the first operand's graph comes first on the tape
+        for child in value.children.borrow().iter().rev() {
+            if !visited.contains(child) {
+                stack.push((child.clone(), false));
+            }
         }
     }
     topo
 }
 
-pub struct Neuron {
+/// The nodes one traversal has reached. Without the `sync` feature this is an
+/// epoch stamped on each node; with it, nodes are shared between threads that
+/// may be walking them at once, so the set is kept by the traversal instead.
+#[cfg(not(feature = "sync"))]
+struct Visited(usize);
+
+#[cfg(not(feature = "sync"))]
+impl Visited {
+    fn new() -> Self {
+        Self(NEXT_EPOCH.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn contains<T: Float>(&self, v: &Value<T>) -> bool {
+        v.visited.get() == self.0
+    }
+
+    /// Marks `v`, returning whether it had not been reached yet.
+    fn insert<T: Float>(&mut self, v: &Value<T>) -> bool {
+        let new = !self.contains(v);
+        v.visited.set(self.0);
+        new
+    }
+}
+
+#[cfg(feature = "sync")]
+struct Visited(std::collections::HashSet<usize>);
+
+#[cfg(feature = "sync")]
+impl Visited {
+    fn new() -> Self {
+        Self(std::collections::HashSet::new())
+    }
+
+    fn contains<T: Float>(&self, v: &Value<T>) -> bool {
+        self.0.contains(&v.id)
+    }
+
+    /// Marks `v`, returning whether it had not been reached yet.
+    fn insert<T: Float>(&mut self, v: &Value<T>) -> bool {
+        self.0.insert(v.id)
+    }
+}
+
+/// Puts back the grads a sweep over `topo` cleared, even if it unwinds. A
+/// finished sweep adds each leaf's old grad to the new one; an interrupted one
+/// restores every cell, so no partial gradient is left behind.
+struct RestoreGrads<'a, T: Float> {
+    topo: &'a [Value<T>],
+    saved: Vec<T>,
+}
+
+impl<T: Float> Drop for RestoreGrads<'_, T> {
+    fn drop(&mut self) {
+        let interrupted = std::thread::panicking();
+        for (v, &old) in self.topo.iter().zip(&self.saved) {
+            if interrupted {
+                v.set_grad(old);
+            } else if v.op.is_none() {
+                v.update_grad(old);
+            }
+        }
+    }
+}
+
+/// Maps the id of each node on `tape` to its position.
+fn tape_positions<T: Float>(tape: &[Value<T>]) -> HashMap<usize, usize> {
+    tape.iter().enumerate().map(|(i, v)| (v.id, i)).collect()
+}
+
+/// Forward-mode differentiation: the directional derivatives of `outputs`
+/// along `tangents`, all computed in a single forward sweep.
+///
+/// Each entry of `tangents` pairs a node with its tangent; every other leaf
+/// has a tangent of zero. For a single input seeded with `1` this gives the
+/// derivative of every output with respect to that input, which would take
+/// one [`Value::backward`] per output in reverse mode. A seeded node is
+/// treated as independent, even if it is itself computed from other nodes.
+/// No `grad` cell is touched.
+pub fn jvp<T: Float>(outputs: &[&Value<T>], tangents: &[(&Value<T>, T)]) -> Vec<T> {
+    let tape = build_tape(outputs);
+    let positions = tape_positions(&tape);
+    let position = |v: &Value<T>| positions.get(&v.id).copied();
+
+    let mut seeded = vec![false; tape.len()];
+    let mut dots = vec![T::zero(); tape.len()];
+    for (v, t) in tangents {
+        if let Some(i) = position(v) {
+            seeded[i] = true;
+            dots[i] = *t;
+        }
+    }
+
+    for (i, v) in tape.iter().enumerate() {
+        if seeded[i] {
+            continue;
+        }
+        // children always come earlier on the tape, so their tangents are done
+        let mut dot = T::zero();
+        let locals = v.local_derivatives();
+        for (child, local) in v.children.borrow().iter().zip(locals) {
+            let child_dot = dots[position(child).unwrap()];
+            // as in `backward_local`, keep 0 * inf from becoming NaN
+            if child_dot != T::zero() && local != T::zero() {
+                dot = dot + local * child_dot;
+            }
+        }
+        dots[i] = dot;
+    }
+
+    outputs.iter().map(|v| dots[position(v).unwrap()]).collect()
+}
+
+/// The Jacobian of `outputs` with respect to `inputs`: entry `[i][j]` is the
+/// derivative of `outputs[i]` with respect to `inputs[j]`.
+///
+/// Uses whichever mode needs fewer sweeps: one [`Value::backward`] per output
+/// or one [`jvp`] per input. Hooks are not run in either mode, so both give
+/// the graph's own derivatives. The `grad` cells of every node involved are
+/// restored afterwards, so gradients accumulated before the call are kept.
+pub fn jacobian<T: Float>(outputs: &[&Value<T>], inputs: &[&Value<T>]) -> Vec<Vec<T>> {
+    if inputs.len() <= outputs.len() {
+        let mut jac = vec![Vec::with_capacity(inputs.len()); outputs.len()];
+        for x in inputs {
+            for (row, dot) in jac.iter_mut().zip(jvp(outputs, &[(x, T::one())])) {
+                row.push(dot);
+            }
+        }
+        return jac;
+    }
+
+    // inputs are included so that an input the outputs don't depend on reads 0
+    // rather than whatever grad it already had
+    let roots: Vec<&Value<T>> = outputs.iter().chain(inputs).copied().collect();
+    let tape = build_tape(&roots);
+    let saved: Vec<T> = tape.iter().map(|v| v.borrow_grad()).collect();
+    let jac = outputs
+        .iter()
+        .map(|y| {
+            for v in tape.iter() {
+                v.set_grad(T::zero());
+            }
+            y.sweep(T::one(), true, false);
+            inputs.iter().map(|x| x.borrow_grad()).collect()
+        })
+        .collect();
+    for (v, grad) in tape.iter().zip(saved) {
+        v.set_grad(grad);
+    }
+    jac
+}
+
+/// The product of the Hessian of `loss` with respect to `params` and the
+/// vector `v`, without forming the Hessian.
+///
+/// Differentiates `loss` with [`Value::grad_graph`] and then pushes `v`
+/// through those gradients with [`jvp`], so no `grad` cell is touched.
+pub fn hvp<T: Float>(loss: &Value<T>, params: &[&Value<T>], v: &[T]) -> Vec<T> {
+    assert_eq!(params.len(), v.len(), "one direction per parameter");
+    let grads = loss.grad_graph(params);
+    let grads: Vec<&Value<T>> = grads.iter().collect();
+    let tangents: Vec<(&Value<T>, T)> = params.iter().copied().zip(v.iter().copied()).collect();
+    jvp(&grads, &tangents)
+}
+
+pub struct Neuron<T: Float = f32> {
     pub nin: usize,
-    pub w: Vec<Value>,
-    pub b: Value,
+    pub w: Vec<Value<T>>,
+    pub b: Value<T>,
 }
 
-impl Neuron {
-    fn new(nin: usize) -> Self {
+impl<T: Float> Neuron<T> {
+    /// Parameters are labelled `{prefix}.w.{k}` and `{prefix}.b`.
+    fn new(nin: usize, prefix: &str) -> Self {
         let mut rng = rand::thread_rng();
+        let mut init = |name: String| Value::new(lit(rng.gen_range(-1.0..1.0))).with_label(name);
         Self {
             nin,
-            w: (0..nin)
-                .map(|_| Value::new(rng.gen_range(-1.0..1.0)))
-                .collect(),
-            b: Value::new(rng.gen_range(-1.0..1.0)),
+            w: (0..nin).map(|k| init(format!("{prefix}.w.{k}"))).collect(),
+            b: init(format!("{prefix}.b")),
         }
     }
 
-    fn forward(&self, x: &[Value]) -> Value {
-        let xw: Vec<Value> = x
-            .iter()
-            .zip(self.w.iter())
-            .map(|(xi, wi)| xi.clone() * wi.clone())
-            .collect();
+    fn forward(&self, x: &[Value<T>]) -> Value<T> {
+        let xw = x.iter().zip(self.w.iter()).map(|(xi, wi)| xi * wi);
 
-        let out = xw.into_iter().fold(self.b.clone(), |a, b| a + b);
+        let out: Value<T> = std::iter::once(self.b.clone()).chain(xw).sum();
         out.tanh()
     }
 
-    fn parameters(&self) -> Vec<&Value> {
+    fn parameters(&self) -> Vec<&Value<T>> {
         let b = vec![&self.b];
         self.w.iter().chain(b).collect()
     }
 }
 
-pub struct Layer {
-    pub neurons: Vec<Neuron>,
+pub struct Layer<T: Float = f32> {
+    pub neurons: Vec<Neuron<T>>,
 }
 
-impl Layer {
-    fn new(nin: usize, nout: usize) -> Self {
+impl<T: Float> Layer<T> {
+    fn new(nin: usize, nout: usize, prefix: &str) -> Self {
         Self {
-            neurons: (0..nout).map(|_| Neuron::new(nin)).collect(),
+            neurons: (0..nout)
+                .map(|j| Neuron::new(nin, &format!("{prefix}.neurons.{j}")))
+                .collect(),
         }
     }
 
-    fn forward(&self, x: &[Value]) -> Vec<Value> {
+    fn forward(&self, x: &[Value<T>]) -> Vec<Value<T>> {
         self.neurons.iter().map(|n| n.forward(x)).collect()
     }
 
-    fn parameters(&self) -> Vec<&Value> {
+    fn parameters(&self) -> Vec<&Value<T>> {
         let mut params = Vec::new();
         for n in self.neurons.iter() {
             params.extend(n.parameters())
@@ -311,27 +1370,43 @@ impl Layer {
     }
 }
 
-pub struct Mlp {
-    pub layers: Vec<Layer>,
+pub struct Mlp<T: Float = f32> {
+    pub layers: Vec<Layer<T>>,
 }
 
-impl Mlp {
+impl<T: Float> Mlp<T> {
+    /// Builds a network with `nin` inputs and a layer of each size in `nouts`.
+    ///
+    /// Parameters are labelled by their path, e.g. `layers.1.neurons.3.w.0`
+    /// for the first weight of the fourth neuron in the second layer.
     pub fn new(nin: usize, nouts: &[usize]) -> Self {
         let mut sizes = vec![nin];
         sizes.extend(nouts);
-        let layers = sizes.windows(2).map(|w| Layer::new(w[0], w[1])).collect();
+        let layers = sizes
+            .windows(2)
+            .enumerate()
+            .map(|(i, w)| Layer::new(w[0], w[1], &format!("layers.{i}")))
+            .collect();
         Self { layers }
     }
 
-    pub fn forward(&self, x: &[Value]) -> Vec<Value> {
-        let mut out: Vec<Value> = x.into();
+    pub fn forward(&self, x: &[Value<T>]) -> Vec<Value<T>> {
+        let mut out: Vec<Value<T>> = x.into();
         for l in self.layers.iter() {
             out = l.forward(&out);
         }
         out
     }
 
-    pub fn parameters(&self) -> Vec<&Value> {
+    /// Evaluates the network on plain numbers without recording a graph. See
+    /// [`no_grad`].
+    pub fn predict(&self, x: &[T]) -> Vec<T> {
+        let _guard = no_grad();
+        let x: Vec<Value<T>> = x.iter().map(|&xi| Value::new(xi)).collect();
+        self.forward(&x).iter().map(|y| y.borrow_data()).collect()
+    }
+
+    pub fn parameters(&self) -> Vec<&Value<T>> {
         let mut params = Vec::new();
         for n in self.layers.iter() {
             params.extend(n.parameters())
@@ -0,0 +1,87 @@
+//! The shared-ownership and interior-mutability primitives behind [`Value`].
+//!
+//! By default these are the single-threaded `Rc` and `RefCell`. With the `sync`
+//! feature they are swapped for `Arc` and `RwLock`, which makes `Value`,
+//! `Neuron`, `Layer` and `Mlp` `Send + Sync` without changing their API.
+//!
+//! [`Value`]: crate::Value
+
+#[cfg(not(feature = "sync"))]
+mod imp {
+    use std::cell::Cell;
+
+    pub use std::{
+        cell::{Ref, RefCell as Lock, RefMut},
+        rc::Rc as Shared,
+    };
+
+    /// Bound on everything stored in a graph. Empty without the `sync` feature.
+    pub trait MaybeSync {}
+
+    impl<T> MaybeSync for T {}
+
+    /// A traversal epoch stamp.
+    #[derive(Debug, Default)]
+    pub struct Mark(Cell<usize>);
+
+    impl Mark {
+        pub fn get(&self) -> usize {
+            self.0.get()
+        }
+
+        pub fn set(&self, epoch: usize) {
+            self.0.set(epoch)
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+mod imp {
+    use std::{
+        fmt,
+        sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    };
+
+    pub use std::sync::Arc as Shared;
+
+    pub type Ref<'a, T> = RwLockReadGuard<'a, T>;
+    pub type RefMut<'a, T> = RwLockWriteGuard<'a, T>;
+
+    /// Bound on everything stored in a graph, so that it can cross threads.
+    pub trait MaybeSync: Send + Sync {}
+
+    impl<T: Send + Sync> MaybeSync for T {}
+
+    /// A `RwLock` with the `borrow`/`borrow_mut` interface of `RefCell`.
+    ///
+    /// A panic while a guard is held cannot leave a plain number in an
+    /// inconsistent state, so poisoning is ignored.
+    #[derive(Default)]
+    pub struct Lock<T>(RwLock<T>);
+
+    impl<T> Lock<T> {
+        pub fn new(value: T) -> Self {
+            Self(RwLock::new(value))
+        }
+
+        pub fn borrow(&self) -> Ref<'_, T> {
+            self.0.read().unwrap_or_else(|e| e.into_inner())
+        }
+
+        pub fn borrow_mut(&self) -> RefMut<'_, T> {
+            self.0.write().unwrap_or_else(|e| e.into_inner())
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.0.get_mut().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+
+    impl<T: fmt::Debug> fmt::Debug for Lock<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_tuple("Lock").field(&*self.borrow()).finish()
+        }
+    }
+}
+
+pub use imp::*;
@@ -1,5 +1,11 @@
+#![allow(clippy::excessive_precision, clippy::approx_constant)]
+
 use super::*;
 
+/// Most tests exercise the default element type; float literals would
+/// otherwise fall back to `f64`.
+type Value = super::Value<f32>;
+
 #[test]
 fn test_add() {
     let v1 = Value::new(1.0);
@@ -7,8 +13,8 @@ fn test_add() {
     let v3 = v1.clone() + v2.clone();
     let res = *v3.data.borrow();
     assert_eq!(res, 3.0);
-    assert_eq!(v3.children[0], v1);
-    assert_eq!(v3.children[1], v2);
+    assert_eq!(v3.children()[0], v1);
+    assert_eq!(v3.children()[1], v2);
     assert_eq!(v3.op, Some(Operation::Add));
 }
 
@@ -21,9 +27,9 @@ fn test_add_twice() {
     let v4 = v1v2.clone() + v3.clone();
     let res = *v4.data.borrow();
     assert_eq!(res, 6.0);
-    assert_eq!(v4.children.len(), 2);
-    assert_eq!(v4.children[0], v1v2);
-    assert_eq!(v4.children[1], v3);
+    assert_eq!(v4.children().len(), 2);
+    assert_eq!(v4.children()[0], v1v2);
+    assert_eq!(v4.children()[1], v3);
 }
 
 #[test]
@@ -31,10 +37,10 @@ fn test_add_then_mut() {
     let v1 = Value::new(1.0);
     let v2 = Value::new(2.0);
     let v3 = v1.clone() + v2.clone();
-    assert_eq!(v3.children[0], v1);
+    assert_eq!(v3.children()[0], v1);
     *v1.data.borrow_mut() += 1.0;
-    assert_eq!(v3.children[0], v1);
-    let res = *v3.children[0].data.borrow();
+    assert_eq!(v3.children()[0], v1);
+    let res = *v3.children()[0].data.borrow();
     assert_eq!(res, 2.0);
 }
 
@@ -45,8 +51,8 @@ fn test_mul() {
     let v3 = v1.clone() * v2.clone();
     let res = *v3.data.borrow();
     assert_eq!(res, 6.0);
-    assert_eq!(v3.children[0], v1);
-    assert_eq!(v3.children[1], v2);
+    assert_eq!(v3.children()[0], v1);
+    assert_eq!(v3.children()[1], v2);
     assert_eq!(v3.op, Some(Operation::Mul));
 }
 
@@ -56,8 +62,8 @@ fn test_tanh() {
     let v2 = v1.tanh();
 
     assert!((v2.borrow_data() - 0.96402758).abs() < 0.00001);
-    assert_eq!(v2.children[0], v1);
-    assert_eq!(v2.children.len(), 1);
+    assert_eq!(v2.children()[0], v1);
+    assert_eq!(v2.children().len(), 1);
     assert_eq!(v2.op, Some(Operation::Tanh));
 }
 
@@ -199,9 +205,9 @@ fn test_grad_update_of_child() {
     let x1 = Value::new(2.0);
     let x2 = x1.tanh();
 
-    assert_eq!(x2.children[0], x1);
+    assert_eq!(x2.children()[0], x1);
 
-    x2.children[0].set_grad(2.0);
+    x2.children()[0].set_grad(2.0);
     assert_eq!(x1.borrow_grad(), 2.0);
 }
 
@@ -212,10 +218,10 @@ fn test_build_topo() {
     let y = x1.clone() + x2.clone();
     let y2 = y.clone() * x1.clone();
     let topo = build_topo(&y2);
-    assert_eq!(&y2, topo[3]);
-    assert_eq!(&y, topo[2]);
-    assert_eq!(&x1, topo[0]);
-    assert_eq!(&x2, topo[1]);
+    assert_eq!(y2, topo[3]);
+    assert_eq!(y, topo[2]);
+    assert_eq!(x1, topo[0]);
+    assert_eq!(x2, topo[1]);
 }
 
 #[test]
@@ -232,8 +238,8 @@ fn test_karpathy_build_topo() {
     let o = n.tanh();
     let topo = build_topo(&o);
 
-    assert_eq!(topo[9], &o);
-    assert_eq!(topo[0], &x1);
+    assert_eq!(topo[9], o);
+    assert_eq!(topo[0], x1);
 }
 
 #[test]
@@ -359,34 +365,34 @@ fn test_new_tanh_grad() {
 
 #[test]
 fn test_tanh_comp() {
-    let mut n = Value::new(0.8814);
-    n.label = Some("n".to_string());
-    let mut e = (n.clone() * 2.0).exp();
-    e.label = Some("e".to_string());
-    let mut o = (e.clone() - 1.0) / (e.clone() + 1.0);
-    o.label = Some("o".to_string());
+    let n = Value::new(0.8814);
+    n.set_label("n");
+    let e = (n.clone() * 2.0).exp();
+    e.set_label("e");
+    let o = (e.clone() - 1.0) / (e.clone() + 1.0);
+    o.set_label("o");
     o.backward();
 
     let n2 = Value::new(0.8814);
     let o2 = n2.tanh();
     o2.backward();
 
-    assert_eq!(o2.borrow_data(), o.borrow_data());
+    assert!((o2.borrow_data() - o.borrow_data()).abs() < 0.000001);
     assert!((n.borrow_grad() - n2.borrow_grad()).abs() < 0.001);
 }
 
 #[test]
 fn test_middle_double_dep() {
-    let mut a = Value::new(1.0);
-    a.label = Some("a".to_string());
-    let mut b = a.clone() + 1.0;
-    b.label = Some("b".to_string());
-    let mut c = b.clone() * 3.0;
-    c.label = Some("c".to_string());
-    let mut d = b.clone() * 4.0;
-    d.label = Some("d".to_string());
-    let mut e = c.clone() * d.clone();
-    e.label = Some("e".to_string());
+    let a = Value::new(1.0);
+    a.set_label("a");
+    let b = a.clone() + 1.0;
+    b.set_label("b");
+    let c = b.clone() * 3.0;
+    c.set_label("c");
+    let d = b.clone() * 4.0;
+    d.set_label("d");
+    let e = c.clone() * d.clone();
+    e.set_label("e");
     e.backward();
 
     assert_eq!(d.borrow_grad(), 6.0);
@@ -424,3 +430,1776 @@ fn test_karpathy_backward_new_tanh() {
     assert!((x2.borrow_grad() - 0.5).abs() < 0.0001);
     assert!((w2.borrow_grad() - 0.0).abs() < 0.0001);
 }
+
+#[test]
+fn test_relu() {
+    let a = Value::new(2.0);
+    let b = a.relu();
+    b.backward();
+    assert_eq!(b.borrow_data(), 2.0);
+    assert_eq!(a.borrow_grad(), 1.0);
+    assert_eq!(b.op, Some(Operation::Relu));
+
+    let a = Value::new(-2.0);
+    let b = a.relu();
+    b.backward();
+    assert_eq!(b.borrow_data(), 0.0);
+    assert_eq!(a.borrow_grad(), 0.0);
+
+    let a = Value::new(0.0);
+    a.relu().backward();
+    assert_eq!(a.borrow_grad(), 0.0);
+
+    assert!(Value::new(f32::NAN).relu().borrow_data().is_nan());
+    assert!(Value::new(f32::NAN).leaky_relu(0.1).borrow_data().is_nan());
+}
+
+#[test]
+fn test_leaky_relu() {
+    let a = Value::new(3.0);
+    let b = a.leaky_relu(0.1);
+    b.backward();
+    assert_eq!(b.borrow_data(), 3.0);
+    assert_eq!(a.borrow_grad(), 1.0);
+    assert_eq!(b.op, Some(Operation::LeakyRelu(0.1)));
+
+    let a = Value::new(-3.0);
+    let b = a.leaky_relu(0.1);
+    b.backward();
+    assert!((b.borrow_data() - -0.3).abs() < 0.00001);
+    assert_eq!(a.borrow_grad(), 0.1);
+}
+
+#[test]
+fn test_sigmoid() {
+    let a = Value::new(0.0);
+    let b = a.sigmoid();
+    b.backward();
+    assert_eq!(b.borrow_data(), 0.5);
+    assert_eq!(a.borrow_grad(), 0.25);
+
+    let a = Value::new(2.0);
+    let b = a.sigmoid();
+    b.backward();
+    assert!((b.borrow_data() - 0.880797).abs() < 0.00001);
+    assert!((a.borrow_grad() - 0.104994).abs() < 0.00001);
+}
+
+#[test]
+fn test_softplus() {
+    let a = Value::new(0.0);
+    let b = a.softplus();
+    b.backward();
+    assert!((b.borrow_data() - 2.0_f32.ln()).abs() < 0.00001);
+    assert_eq!(a.borrow_grad(), 0.5);
+
+    let a = Value::new(-1.0);
+    let b = a.softplus();
+    b.backward();
+    assert!((b.borrow_data() - 0.313262).abs() < 0.00001);
+    assert!((a.borrow_grad() - 0.268941).abs() < 0.00001);
+}
+
+#[test]
+fn test_ln() {
+    let a = Value::new(2.0);
+    let b = a.ln();
+    b.backward();
+    assert!((b.borrow_data() - 2.0_f32.ln()).abs() < 0.00001);
+    assert_eq!(a.borrow_grad(), 0.5);
+    assert_eq!(b.op, Some(Operation::Ln));
+}
+
+#[test]
+fn test_ln_domain_edges() {
+    let a = Value::new(0.0);
+    let b = a.ln();
+    b.backward();
+    assert_eq!(b.borrow_data(), f32::NEG_INFINITY);
+    assert_eq!(a.borrow_grad(), f32::INFINITY);
+
+    let a = Value::new(-1.0);
+    let b = a.ln();
+    b.backward();
+    assert!(b.borrow_data().is_nan());
+    assert!((a.borrow_grad() - -1.0).abs() < 0.00001);
+}
+
+#[test]
+fn test_log() {
+    let a = Value::new(8.0);
+    let b = a.log(2.0);
+    b.backward();
+    assert!((b.borrow_data() - 3.0).abs() < 0.00001);
+    assert!((a.borrow_grad() - 1.0 / (8.0 * 2.0_f32.ln())).abs() < 0.00001);
+    assert_eq!(b.op, Some(Operation::Log(2.0)));
+
+    let a = Value::new(0.0);
+    let b = a.log(10.0);
+    b.backward();
+    assert_eq!(b.borrow_data(), f32::NEG_INFINITY);
+    assert_eq!(a.borrow_grad(), f32::INFINITY);
+
+    let a = Value::new(-1.0);
+    let b = a.log(10.0);
+    b.backward();
+    assert!(b.borrow_data().is_nan());
+    assert!((a.borrow_grad() - -1.0 / 10.0_f32.ln()).abs() < 0.00001);
+}
+
+#[test]
+fn test_sqrt() {
+    let a = Value::new(4.0);
+    let b = a.sqrt();
+    b.backward();
+    assert_eq!(b.borrow_data(), 2.0);
+    assert_eq!(a.borrow_grad(), 0.25);
+
+    let a = Value::new(0.0);
+    let b = a.sqrt();
+    b.backward();
+    assert_eq!(b.borrow_data(), 0.0);
+    assert_eq!(a.borrow_grad(), f32::INFINITY);
+
+    let a = Value::new(-4.0);
+    let b = a.sqrt();
+    b.backward();
+    assert!(b.borrow_data().is_nan());
+    assert!(a.borrow_grad().is_nan());
+}
+
+#[test]
+fn test_abs() {
+    let a = Value::new(-3.0);
+    let b = a.abs();
+    b.backward();
+    assert_eq!(b.borrow_data(), 3.0);
+    assert_eq!(a.borrow_grad(), -1.0);
+
+    let a = Value::new(3.0);
+    a.abs().backward();
+    assert_eq!(a.borrow_grad(), 1.0);
+
+    let a = Value::new(0.0);
+    let b = a.abs();
+    b.backward();
+    assert_eq!(b.borrow_data(), 0.0);
+    assert_eq!(a.borrow_grad(), 0.0);
+}
+
+#[test]
+fn test_powf() {
+    let x = Value::new(2.0);
+    let n = Value::new(3.0);
+    let y = x.powf(&n);
+    y.backward();
+    assert_eq!(y.borrow_data(), 8.0);
+    assert_eq!(y.op, Some(Operation::Pow));
+    assert_eq!(y.children()[1], n);
+    assert!((x.borrow_grad() - 12.0).abs() < 0.0001);
+    assert!((n.borrow_grad() - 8.0 * 2.0_f32.ln()).abs() < 0.0001);
+}
+
+#[test]
+fn test_powf_fractional() {
+    let x = Value::new(4.0);
+    let n = Value::new(0.5);
+    let y = x.powf(&n);
+    y.backward();
+    assert_eq!(y.borrow_data(), 2.0);
+    assert!((x.borrow_grad() - 0.25).abs() < 0.0001);
+    assert!((n.borrow_grad() - 2.0 * 4.0_f32.ln()).abs() < 0.0001);
+}
+
+#[test]
+fn test_powf_zero_base() {
+    let x = Value::new(0.0);
+    let n = Value::new(2.0);
+    let y = x.powf(&n);
+    y.backward();
+    assert_eq!(y.borrow_data(), 0.0);
+    assert_eq!(x.borrow_grad(), 0.0);
+    assert_eq!(n.borrow_grad(), 0.0);
+
+    // 0^0 is 1 and constant in x
+    let x = Value::new(0.0);
+    let y = x.pow(0);
+    y.backward();
+    assert_eq!(y.borrow_data(), 1.0);
+    assert_eq!(x.borrow_grad(), 0.0);
+    assert_eq!(y.grad_graph(&[&x])[0].borrow_data(), 0.0);
+    assert_eq!(jvp(&[&y], &[(&x, 1.0)]), vec![0.0]);
+
+    // away from x = 0 a zero exponent still has a mixed partial:
+    // d/dn (n * x^(n-1)) = x^(n-1) at n = 0
+    let x = Value::new(2.0);
+    let n = Value::new(0.0);
+    let y = x.powf(&n);
+    let dydx = &y.grad_graph(&[&x])[0];
+    assert_eq!(dydx.borrow_data(), 0.0);
+    assert_eq!(dydx.grad_graph(&[&n])[0].borrow_data(), 0.5);
+    assert_eq!(hvp(&y, &[&x, &n], &[0.0, 1.0])[0], 0.5);
+}
+
+#[test]
+fn test_powf_negative_base() {
+    let x = Value::new(-2.0);
+    let n = Value::new(3.0);
+    let y = x.powf(&n);
+    y.backward();
+    assert_eq!(y.borrow_data(), -8.0);
+    assert!((x.borrow_grad() - 12.0).abs() < 0.0001);
+    assert_eq!(n.borrow_grad(), 0.0);
+
+    let x = Value::new(-2.0);
+    let y = x.powf(&Value::new(0.5));
+    assert!(y.borrow_data().is_nan());
+}
+
+#[test]
+fn test_pow_exponent_grad() {
+    let x = Value::new(3.0);
+    let y = x.pow(2);
+    y.backward();
+    assert_eq!(x.borrow_grad(), 6.0);
+    assert!((y.children()[1].borrow_grad() - 9.0 * 3.0_f32.ln()).abs() < 0.0001);
+}
+
+#[test]
+fn test_sin() {
+    let a = Value::new(0.5);
+    let b = a.sin();
+    b.backward();
+    assert_eq!(b.borrow_data(), 0.5_f32.sin());
+    assert_eq!(a.borrow_grad(), 0.5_f32.cos());
+    assert_eq!(b.op, Some(Operation::Sin));
+}
+
+#[test]
+fn test_cos() {
+    let a = Value::new(0.5);
+    let b = a.cos();
+    b.backward();
+    assert_eq!(b.borrow_data(), 0.5_f32.cos());
+    assert_eq!(a.borrow_grad(), -(0.5_f32.sin()));
+}
+
+#[test]
+fn test_tan() {
+    let a = Value::new(0.5);
+    let b = a.tan();
+    b.backward();
+    assert_eq!(b.borrow_data(), 0.5_f32.tan());
+    assert!((a.borrow_grad() - 1.0 / 0.5_f32.cos().powi(2)).abs() < 0.0001);
+}
+
+#[test]
+fn test_sinh_cosh() {
+    let a = Value::new(1.5);
+    let b = a.sinh();
+    b.backward();
+    assert_eq!(b.borrow_data(), 1.5_f32.sinh());
+    assert_eq!(a.borrow_grad(), 1.5_f32.cosh());
+
+    let a = Value::new(1.5);
+    let b = a.cosh();
+    b.backward();
+    assert_eq!(b.borrow_data(), 1.5_f32.cosh());
+    assert_eq!(a.borrow_grad(), 1.5_f32.sinh());
+}
+
+#[test]
+fn test_atan2() {
+    let y = Value::new(1.0);
+    let x = Value::new(-2.0);
+    let a = y.atan2(&x);
+    a.backward();
+    assert_eq!(a.borrow_data(), 1.0_f32.atan2(-2.0));
+    assert!((y.borrow_grad() - -2.0 / 5.0).abs() < 0.0001);
+    assert!((x.borrow_grad() - -1.0 / 5.0).abs() < 0.0001);
+    assert_eq!(a.op, Some(Operation::Atan2));
+
+    let y = Value::new(0.0);
+    let x = Value::new(0.0);
+    y.atan2(&x).backward();
+    assert_eq!(y.borrow_grad(), 0.0);
+    assert_eq!(x.borrow_grad(), 0.0);
+}
+
+#[test]
+fn test_fit_periodic_target() {
+    // recover the frequency of sin(w * t) from samples
+    let w = Value::new(1.2);
+    let ts = [0.1, 0.4, 0.7, 1.0, 1.3];
+    for _ in 0..200 {
+        let loss = ts
+            .iter()
+            .map(|&t| ((w.clone() * t).sin() - (1.5 * t).sin()).pow(2))
+            .fold(Value::new(0.0), |a, b| a + b);
+        w.set_grad(0.0);
+        loss.backward();
+        *w.borrow_data_mut() -= 0.1 * w.borrow_grad();
+    }
+    assert!((w.borrow_data() - 1.5).abs() < 0.01);
+}
+
+const EXTREME_INPUTS: [f32; 15] = [
+    f32::MIN,
+    -1000.0,
+    -100.0,
+    -89.0,
+    -45.0,
+    -20.0,
+    -1e-30,
+    0.0,
+    1e-30,
+    20.0,
+    45.0,
+    89.0,
+    100.0,
+    1000.0,
+    f32::MAX,
+];
+
+#[test]
+fn test_tanh_extreme_inputs() {
+    for x in EXTREME_INPUTS {
+        let a = Value::new(x);
+        let b = a.tanh();
+        b.backward();
+        assert!(b.borrow_data().abs() <= 1.0, "tanh({x}) = {}", b);
+        assert!(a.borrow_grad().is_finite(), "tanh'({x}) = {}", a);
+    }
+    assert_eq!(Value::new(100.0).tanh().borrow_data(), 1.0);
+    assert_eq!(Value::new(-100.0).tanh().borrow_data(), -1.0);
+}
+
+#[test]
+fn test_exp_extreme_inputs() {
+    for x in EXTREME_INPUTS {
+        let a = Value::new(x);
+        let b = a.exp();
+        b.backward();
+        assert!(b.borrow_data() >= 0.0, "exp({x}) = {}", b);
+        assert!(!a.borrow_grad().is_nan(), "exp'({x}) = {}", a);
+    }
+}
+
+#[test]
+fn test_sigmoid_extreme_inputs() {
+    for x in EXTREME_INPUTS {
+        let a = Value::new(x);
+        let b = a.sigmoid();
+        b.backward();
+        assert!(
+            (0.0..=1.0).contains(&b.borrow_data()),
+            "sigmoid({x}) = {}",
+            b
+        );
+        assert!(a.borrow_grad().is_finite(), "sigmoid'({x}) = {}", a);
+    }
+}
+
+#[test]
+fn test_softplus_extreme_inputs() {
+    for x in EXTREME_INPUTS {
+        let a = Value::new(x);
+        let b = a.softplus();
+        b.backward();
+        assert!(b.borrow_data().is_finite(), "softplus({x}) = {}", b);
+        assert!(b.borrow_data() >= 0.0, "softplus({x}) = {}", b);
+        assert!(a.borrow_grad().is_finite(), "softplus'({x}) = {}", a);
+    }
+    assert_eq!(Value::new(1000.0).softplus().borrow_data(), 1000.0);
+}
+
+#[test]
+fn test_zero_upstream_grad_through_overflowing_exp() {
+    // exp overflows to inf, but a zero upstream gradient must not become NaN
+    let a = Value::new(100.0);
+    let b = a.exp() * 0.0;
+    b.backward();
+    assert_eq!(a.borrow_grad(), 0.0);
+}
+
+#[test]
+fn test_saturated_neuron_backward() {
+    let x = Value::new(50.0);
+    let w = Value::new(2.0);
+    let b = Value::new(1.0);
+    let o = (x.clone() * w.clone() + b.clone()).tanh();
+    o.backward();
+    assert_eq!(o.borrow_data(), 1.0);
+    for v in [&x, &w, &b] {
+        assert!(v.borrow_grad().is_finite(), "{}", v);
+    }
+}
+
+#[test]
+fn test_sum() {
+    let a = Value::new(1.0);
+    let b = Value::new(2.0);
+    let c = Value::new(3.0);
+    let s: Value = vec![a.clone(), b.clone(), c.clone()].into_iter().sum();
+    s.backward();
+    assert_eq!(s.borrow_data(), 6.0);
+    assert_eq!(s.op, Some(Operation::Add));
+    assert_eq!(s.children().len(), 3);
+    assert_eq!(build_topo(&s).len(), 4);
+    for v in [&a, &b, &c] {
+        assert_eq!(v.borrow_grad(), 1.0);
+    }
+}
+
+#[test]
+fn test_sum_by_ref() {
+    let a = Value::new(1.0);
+    let b = Value::new(-4.0);
+    let xs = [a.clone(), b.clone(), a.clone()];
+    let s: Value = xs.iter().sum();
+    s.backward();
+    assert_eq!(s.borrow_data(), -2.0);
+    assert_eq!(a.borrow_grad(), 2.0);
+    assert_eq!(b.borrow_grad(), 1.0);
+}
+
+#[test]
+fn test_sum_empty_and_single() {
+    let s: Value = std::iter::empty::<Value>().sum();
+    assert_eq!(s.borrow_data(), 0.0);
+    assert!(s.children().is_empty());
+    assert_eq!(s.op, None);
+
+    let a = Value::new(5.0);
+    let s: Value = std::iter::once(a.clone()).sum();
+    assert_eq!(s, a);
+}
+
+#[test]
+fn test_product() {
+    let a = Value::new(2.0);
+    let b = Value::new(3.0);
+    let c = Value::new(4.0);
+    let p: Value = [&a, &b, &c].into_iter().product();
+    p.backward();
+    assert_eq!(p.borrow_data(), 24.0);
+    assert_eq!(p.op, Some(Operation::Mul));
+    assert_eq!(p.children().len(), 3);
+    assert_eq!(a.borrow_grad(), 12.0);
+    assert_eq!(b.borrow_grad(), 8.0);
+    assert_eq!(c.borrow_grad(), 6.0);
+
+    let p: Value = std::iter::empty::<Value>().product();
+    assert_eq!(p.borrow_data(), 1.0);
+}
+
+#[test]
+fn test_product_with_zero_factor() {
+    let a = Value::new(0.0);
+    let b = Value::new(3.0);
+    let c = Value::new(4.0);
+    let p: Value = vec![a.clone(), b.clone(), c.clone()].into_iter().product();
+    p.backward();
+    assert_eq!(p.borrow_data(), 0.0);
+    assert_eq!(a.borrow_grad(), 12.0);
+    assert_eq!(b.borrow_grad(), 0.0);
+    assert_eq!(c.borrow_grad(), 0.0);
+}
+
+#[test]
+fn test_neuron_forward_uses_single_sum_node() {
+    let n = Neuron::new(3, "n");
+    let x = [Value::new(1.0), Value::new(2.0), Value::new(3.0)];
+    let out = n.forward(&x);
+    let sum = &out.children()[0];
+    assert_eq!(sum.op, Some(Operation::Add));
+    assert_eq!(sum.children().len(), 4);
+    assert_eq!(sum.children()[0], n.b);
+}
+
+#[test]
+fn test_ref_ops() {
+    let a = Value::new(6.0);
+    let b = Value::new(3.0);
+    assert_eq!((&a + &b).borrow_data(), 9.0);
+    assert_eq!((&a - &b).borrow_data(), 3.0);
+    assert_eq!((&a * &b).borrow_data(), 18.0);
+    assert_eq!((&a / &b).borrow_data(), 2.0);
+    assert_eq!((-&a).borrow_data(), -6.0);
+    assert_eq!((a.clone() + &b).borrow_data(), 9.0);
+    assert_eq!((&a - b.clone()).borrow_data(), 3.0);
+
+    let c = &a * &b;
+    assert_eq!(c.children()[0], a);
+    assert_eq!(c.children()[1], b);
+}
+
+#[test]
+fn test_scalar_ops() {
+    let a = Value::new(4.0);
+    assert_eq!((&a + 2.0).borrow_data(), 6.0);
+    assert_eq!((2.0 + &a).borrow_data(), 6.0);
+    assert_eq!((&a - 1.0).borrow_data(), 3.0);
+    assert_eq!((1.0 - &a).borrow_data(), -3.0);
+    assert_eq!((&a * 2.0).borrow_data(), 8.0);
+    assert_eq!((2.0 * &a).borrow_data(), 8.0);
+    assert_eq!((&a / 2.0).borrow_data(), 2.0);
+    assert_eq!((2.0 / &a).borrow_data(), 0.5);
+    assert_eq!((2.0 * a.clone()).borrow_data(), 8.0);
+    assert_eq!((1.0 - a.clone()).borrow_data(), -3.0);
+    assert_eq!((a.clone() / 2.0).borrow_data(), 2.0);
+    assert_eq!((2.0 / a.clone()).borrow_data(), 0.5);
+}
+
+#[test]
+fn test_scalar_ops_grad() {
+    let a = Value::new(4.0);
+    let b = 2.0 / &a + 3.0 * &a - &a / 2.0 + (1.0 - &a);
+    b.backward();
+    assert!((a.borrow_grad() - (-2.0 / 16.0 + 3.0 - 0.5 - 1.0)).abs() < 0.00001);
+}
+
+#[test]
+fn test_compound_assign() {
+    let a = Value::new(2.0);
+    let b = Value::new(3.0);
+    let mut acc = a.clone();
+    acc += &b;
+    acc *= 2.0;
+    acc -= &a;
+    acc /= b.clone();
+    assert_eq!(acc.borrow_data(), 8.0 / 3.0);
+    acc.backward();
+    // acc = (2 (a + b) - a) / b = (a + 2b) / b
+    assert!((a.borrow_grad() - 1.0 / 3.0).abs() < 0.00001);
+    assert!((b.borrow_grad() - -2.0 / 9.0).abs() < 0.00001);
+}
+
+#[test]
+fn test_build_topo_puts_children_first() {
+    /// Every node comes after all of its children.
+    fn assert_children_first(topo: &[Value]) {
+        for (i, v) in topo.iter().enumerate() {
+            for child in v.children() {
+                let j = topo.iter().position(|t| *t == child).unwrap();
+                assert!(j < i, "child at {} after its parent at {}", j, i);
+            }
+        }
+    }
+
+    let a = Value::new(1.0);
+    let b = Value::new(2.0);
+    let c = &a * &b;
+    let d = &c + &a;
+    let e = &c * &d;
+    let topo = build_topo(&e);
+    assert_eq!(topo.len(), 5);
+    assert_children_first(&topo);
+    assert_eq!(topo.last(), Some(&e));
+
+    // not creation order: `x` is created first but reached last
+    let x = Value::new(1.0);
+    let y = Value::new(2.0);
+    let z = &y.exp() + &x.exp();
+    let topo = build_topo(&z);
+    assert_eq!(topo.len(), 5);
+    assert_children_first(&topo);
+
+    // a node shared between two roots is visited afresh for each tape
+    let f = &c + 1.0;
+    assert_eq!(build_topo(&f).len(), 5);
+    assert_eq!(build_topo(&e).len(), 5);
+}
+
+#[test]
+fn test_f64_backward() {
+    let x1 = super::Value::new(2.0_f64);
+    let w1 = super::Value::new(-3.0);
+    let b = super::Value::new(6.8813735870195432);
+    let o = (&x1 * &w1 + &b).tanh();
+    o.backward();
+
+    let n = 2.0 * -3.0 + 6.8813735870195432_f64;
+    assert!((o.borrow_data() - n.tanh()).abs() < 1e-12);
+    assert!((x1.borrow_grad() - -3.0 * (1.0 - n.tanh().powi(2))).abs() < 1e-12);
+    assert!((w1.borrow_grad() - 2.0 * (1.0 - n.tanh().powi(2))).abs() < 1e-12);
+}
+
+#[test]
+fn test_f64_scalar_ops() {
+    let a = super::Value::new(4.0_f64);
+    let b = 2.0 / &a + 3.0 * &a - &a / 2.0 + (1.0 - &a);
+    b.backward();
+    assert!((a.borrow_grad() - (-2.0 / 16.0 + 3.0 - 0.5 - 1.0)).abs() < 1e-12);
+}
+
+#[test]
+fn test_f64_mlp() {
+    let m: Mlp<f64> = Mlp::new(3, &[4, 1]);
+    let x = [2.0, 3.0, -1.0].map(super::Value::new);
+    let loss = (&m.forward(&x)[0] - 1.0).pow(2);
+    loss.backward();
+    assert_eq!(m.parameters().len(), 4 * 4 + 5);
+    assert!(m.parameters().iter().all(|p| p.borrow_grad().is_finite()));
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn test_value_and_mlp_are_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Value>();
+    assert_send_sync::<super::Value<f64>>();
+    assert_send_sync::<Mlp>();
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn test_concurrent_forward_backward_on_shared_model() {
+    let m: Mlp = Mlp::new(3, &[4, 4, 1]);
+    let xs = [
+        [2.0, 3.0, -1.0],
+        [3.0, -1.0, 0.5],
+        [0.5, 1.0, 1.0],
+        [1.0, 1.0, -1.0],
+    ];
+    let loss = |x: &[f32; 3]| {
+        let x = x.map(Value::new);
+        (&m.forward(&x)[0] - 1.0).pow(2)
+    };
+
+    // reference: the same passes, one after another
+    for x in xs.iter() {
+        loss(x).backward();
+    }
+    let expected: Vec<f32> = m.parameters().iter().map(|p| p.borrow_grad()).collect();
+    for p in m.parameters() {
+        p.set_grad(0.0);
+    }
+
+    std::thread::scope(|s| {
+        for x in xs.iter() {
+            s.spawn(|| {
+                for _ in 0..50 {
+                    let l = loss(x);
+                    assert!(l.borrow_data().is_finite());
+                }
+                loss(x).backward();
+            });
+        }
+    });
+
+    for (p, e) in m.parameters().iter().zip(expected) {
+        assert!((p.borrow_grad() - e).abs() < 0.0001, "{} vs {}", p, e);
+    }
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn test_concurrent_tapes_over_shared_graph() {
+    let x = Value::new(0.5);
+    let y = (0..20).fold(x.clone(), |acc, _| &acc.tanh() + &x);
+    let len = build_topo(&y).len();
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..200 {
+                    let topo = build_topo(&y);
+                    assert_eq!(topo.len(), len);
+                    assert_eq!(topo.last(), Some(&y));
+                }
+            });
+        }
+    });
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn test_value_moves_between_threads() {
+    let a = Value::new(2.0);
+    let b = std::thread::spawn({
+        let a = a.clone();
+        move || a.exp() * 3.0
+    })
+    .join()
+    .unwrap();
+    b.backward();
+    assert!((a.borrow_grad() - 3.0 * 2.0_f32.exp()).abs() < 0.0001);
+}
+
+#[test]
+fn test_no_grad_skips_graph() {
+    let a = Value::new(2.0);
+    let b = Value::new(3.0);
+    let c = {
+        let _guard = no_grad();
+        assert!(!is_grad_enabled());
+        (&a * &b + 1.0).tanh()
+    };
+    assert!(is_grad_enabled());
+    assert!((c.borrow_data() - 7.0_f32.tanh()).abs() < 0.00001);
+    assert!(c.children().is_empty());
+    assert_eq!(c.op, None);
+
+    c.backward();
+    assert_eq!(a.borrow_grad(), 0.0);
+    assert_eq!(b.borrow_grad(), 0.0);
+
+    // recording resumes once the guard is gone
+    let d = &a * &b;
+    assert_eq!(d.children().len(), 2);
+}
+
+/// Counts the allocations made on each thread, so that a test can check
+/// what an operation allocates while other tests run alongside.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+unsafe impl std::alloc::GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+fn allocations<R>(f: impl FnOnce() -> R) -> usize {
+    let before = ALLOCATIONS.with(|n| n.get());
+    let r = f();
+    let after = ALLOCATIONS.with(|n| n.get());
+    drop(r);
+    after - before
+}
+
+#[test]
+fn test_no_grad_skips_operand_allocation() {
+    let a = Value::new(2.0);
+    let b = Value::new(3.0);
+    let leaf = allocations(|| Value::new(1.0));
+    let _guard = no_grad();
+    // an op allocates exactly what a leaf does: no operand vector
+    assert_eq!(allocations(|| a.tanh()), leaf);
+    assert_eq!(allocations(|| &a + &b), leaf);
+    assert_eq!(allocations(|| a.max(&b)), leaf);
+    assert_eq!(allocations(|| a.pow(2)), leaf);
+}
+
+#[test]
+fn test_no_grad_nested() {
+    let outer = no_grad();
+    {
+        let _inner = no_grad();
+        assert!(!is_grad_enabled());
+    }
+    assert!(!is_grad_enabled());
+    drop(outer);
+    assert!(is_grad_enabled());
+}
+
+#[test]
+fn test_mlp_predict() {
+    let m: Mlp = Mlp::new(3, &[4, 4, 1]);
+    let x = [2.0, 3.0, -1.0];
+    let y = m.forward(&x.map(Value::new));
+    assert_eq!(m.predict(&x), vec![y[0].borrow_data()]);
+    assert!(is_grad_enabled());
+}
+
+#[test]
+fn test_labels() {
+    let x = Value::new(2.0);
+    assert_eq!(x.label(), None);
+    x.set_label("x");
+    assert_eq!(x.label().as_deref(), Some("x"));
+    let y = (&x * 3.0).with_label("y");
+    assert_eq!(y.label().as_deref(), Some("y"));
+    assert_eq!(y.to_string(), "Value(data=6, label=y, grad=0)");
+    // a clone is the same node, so it shares the label
+    y.clone().set_label("z");
+    assert_eq!(y.label().as_deref(), Some("z"));
+}
+
+#[test]
+fn test_mlp_parameter_labels() {
+    let m: Mlp = Mlp::new(2, &[3, 1]);
+    let labels: Vec<String> = m.parameters().iter().map(|p| p.label().unwrap()).collect();
+    assert_eq!(labels.len(), 3 * 3 + 4);
+    assert_eq!(labels[0], "layers.0.neurons.0.w.0");
+    assert_eq!(labels[2], "layers.0.neurons.0.b");
+    assert_eq!(labels[7], "layers.0.neurons.2.w.1");
+    assert_eq!(labels[12], "layers.1.neurons.0.b");
+    assert_eq!(
+        m.layers[1].neurons[0].w[2].label().as_deref(),
+        Some("layers.1.neurons.0.w.2")
+    );
+}
+
+#[test]
+fn test_detach() {
+    let a = Value::new(2.0);
+    let b = &a * 3.0;
+    let d = b.detach();
+    assert!(d.children().is_empty());
+    assert_eq!(d.op, None);
+    assert_eq!(d.borrow_data(), 6.0);
+
+    // the data cell is shared in both directions
+    *b.borrow_data_mut() = 7.0;
+    assert_eq!(d.borrow_data(), 7.0);
+    *d.borrow_data_mut() = 8.0;
+    assert_eq!(b.borrow_data(), 8.0);
+
+    // but gradients are not
+    let out = &d * &a;
+    out.backward();
+    assert_eq!(a.borrow_grad(), 8.0);
+    assert_eq!(b.borrow_grad(), 0.0);
+}
+
+#[test]
+fn test_stop_gradient() {
+    let a = Value::new(2.0);
+    let b = a.pow(2).stop_gradient();
+    assert_eq!(b.borrow_data(), 4.0);
+    assert_eq!(b.op, Some(Operation::StopGradient));
+    assert_eq!(b.children().len(), 1);
+
+    let out = &b * &a;
+    out.backward();
+    assert_eq!(out.borrow_data(), 8.0);
+    assert_eq!(b.borrow_grad(), 2.0);
+    assert_eq!(b.children()[0].borrow_grad(), 0.0);
+    assert_eq!(a.borrow_grad(), 4.0);
+}
+
+#[test]
+fn test_straight_through_estimator() {
+    // forward rounds, backward pretends the rounding was the identity
+    let x = Value::new(1.3);
+    let rounded = Value::new(x.borrow_data().round());
+    let y = &x + (&rounded - &x).stop_gradient();
+    let out = &y * 2.0;
+    out.backward();
+    assert_eq!(y.borrow_data(), 1.0);
+    assert_eq!(x.borrow_grad(), 2.0);
+}
+
+#[test]
+fn test_grad_graph_matches_backward() {
+    let a = Value::new(-2.0);
+    let b = Value::new(3.0);
+    let f = (&a * &b) * (&a + &b);
+    let grads = f.grad_graph(&[&a, &b]);
+    assert_eq!(grads[0].borrow_data(), -3.0);
+    assert_eq!(grads[1].borrow_data(), -8.0);
+    // nothing is written to the grad cells
+    assert_eq!(a.borrow_grad(), 0.0);
+    assert_eq!(f.borrow_grad(), 0.0);
+
+    let c = Value::new(1.0);
+    assert_eq!(f.grad_graph(&[&c])[0].borrow_data(), 0.0);
+}
+
+#[test]
+fn test_second_derivative_tanh() {
+    let x = Value::new(0.7);
+    let y = x.tanh();
+    let dy = &y.grad_graph(&[&x])[0];
+    let d2y = &dy.grad_graph(&[&x])[0];
+
+    let t = 0.7_f32.tanh();
+    assert!((dy.borrow_data() - (1.0 - t * t)).abs() < 0.00001);
+    assert!((d2y.borrow_data() - -2.0 * t * (1.0 - t * t)).abs() < 0.00001);
+}
+
+#[test]
+fn test_second_derivative_exp() {
+    let x = Value::new(1.5);
+    let y = (&x * 2.0).exp();
+    let dy = &y.grad_graph(&[&x])[0];
+    let d2y = &dy.grad_graph(&[&x])[0];
+    let d3y = &d2y.grad_graph(&[&x])[0];
+
+    let e = 3.0_f32.exp();
+    assert!((dy.borrow_data() - 2.0 * e).abs() < 0.001);
+    assert!((d2y.borrow_data() - 4.0 * e).abs() < 0.001);
+    assert!((d3y.borrow_data() - 8.0 * e).abs() < 0.001);
+}
+
+#[test]
+fn test_second_derivative_pow() {
+    let x = Value::new(2.0);
+    let y = x.pow(3);
+    let dy = &y.grad_graph(&[&x])[0];
+    let d2y = &dy.grad_graph(&[&x])[0];
+    assert!((dy.borrow_data() - 12.0).abs() < 0.0001);
+    assert!((d2y.borrow_data() - 12.0).abs() < 0.0001);
+
+    // with the exponent in the graph: d2/dn2 x^n = x^n ln(x)^2 and
+    // d2/dxdn x^n = x^(n-1) (1 + n ln(x))
+    let n = Value::new(3.0);
+    let y = x.powf(&n);
+    let dn = &y.grad_graph(&[&n])[0];
+    let second = dn.grad_graph(&[&x, &n]);
+    let ln2 = 2.0_f32.ln();
+    assert!((second[0].borrow_data() - 4.0 * (1.0 + 3.0 * ln2)).abs() < 0.0001);
+    assert!((second[1].borrow_data() - 8.0 * ln2 * ln2).abs() < 0.0001);
+}
+
+#[test]
+fn test_grad_graph_backward_gives_hessian_row() {
+    // f = x^2 y, so df/dx = 2xy and d2f/dx2 = 2y, d2f/dxdy = 2x
+    let x = Value::new(3.0);
+    let y = Value::new(5.0);
+    let f = x.pow(2) * &y;
+    let dfdx = &f.grad_graph(&[&x])[0];
+    assert!((dfdx.borrow_data() - 30.0).abs() < 0.0001);
+    dfdx.backward();
+    assert!((x.borrow_grad() - 10.0).abs() < 0.0001);
+    assert!((y.borrow_grad() - 6.0).abs() < 0.0001);
+}
+
+#[test]
+fn test_newton_step() {
+    // minimise (x - 2)^4 + x^2 with a few Newton iterations
+    let x = Value::new(0.0);
+    for _ in 0..20 {
+        let f = (&x - 2.0).pow(4) + x.pow(2);
+        let g = &f.grad_graph(&[&x])[0];
+        let h = &g.grad_graph(&[&x])[0];
+        let step = g.borrow_data() / h.borrow_data();
+        *x.borrow_data_mut() -= step;
+    }
+    // stationary point of 4(x - 2)^3 + 2x
+    let x = x.borrow_data();
+    assert!((4.0 * (x - 2.0).powi(3) + 2.0 * x).abs() < 0.0001);
+}
+
+/// Checks that a one-hot `jvp` for each input reproduces the gradient that
+/// `backward` accumulated into it.
+fn assert_jvp_matches_backward(out: &Value, inputs: &[&Value]) {
+    for x in inputs {
+        let dot = jvp(&[out], &[(x, 1.0)])[0];
+        assert!(
+            (dot - x.borrow_grad()).abs() < 0.0001,
+            "jvp {} vs backward {}",
+            dot,
+            x
+        );
+    }
+}
+
+#[test]
+fn test_jvp_karpathy_neuron() {
+    let x1 = Value::new(2.0);
+    let x2 = Value::new(0.0);
+    let w1 = Value::new(-3.0);
+    let w2 = Value::new(1.0);
+    let b = Value::new(6.8813735870195432);
+    let n = x1.clone() * w1.clone() + x2.clone() * w2.clone() + b.clone();
+    let o = n.tanh();
+    o.backward();
+    assert_jvp_matches_backward(&o, &[&x1, &x2, &w1, &w2, &b]);
+}
+
+#[test]
+fn test_jvp_karpathy_expressions() {
+    let a = Value::new(-2.0);
+    let b = Value::new(3.0);
+    let f = (a.clone() * b.clone()) * (a.clone() + b.clone());
+    f.backward();
+    assert_jvp_matches_backward(&f, &[&a, &b]);
+
+    let a = Value::new(1.0);
+    let b = a.clone() + 1.0;
+    let e = (b.clone() * 3.0) * (b.clone() * 4.0);
+    e.backward();
+    assert_jvp_matches_backward(&e, &[&a, &b]);
+
+    let n = Value::new(0.8814);
+    let e = (n.clone() * 2.0).exp();
+    let o = (e.clone() - 1.0) / (e.clone() + 1.0);
+    o.backward();
+    assert_jvp_matches_backward(&o, &[&n, &e]);
+}
+
+#[test]
+fn test_jvp_many_outputs_single_input() {
+    let x = Value::new(0.5);
+    let outputs = [x.sin(), x.exp(), x.pow(3), x.sigmoid().ln()];
+    let refs: Vec<&Value> = outputs.iter().collect();
+    let dots = jvp(&refs, &[(&x, 1.0)]);
+    let expected = [
+        0.5_f32.cos(),
+        0.5_f32.exp(),
+        3.0 * 0.25,
+        1.0 - 1.0 / (1.0 + (-0.5_f32).exp()),
+    ];
+    for (dot, e) in dots.iter().zip(expected) {
+        assert!((dot - e).abs() < 0.0001, "{} vs {}", dot, e);
+    }
+    assert_eq!(x.borrow_grad(), 0.0);
+}
+
+#[test]
+fn test_jvp_directional() {
+    let x = Value::new(1.0);
+    let y = Value::new(2.0);
+    let f = x.pow(2) * &y;
+    // grad f = (2xy, x^2) = (4, 1), along (3, -1) gives 11
+    let dot = jvp(&[&f], &[(&x, 3.0), (&y, -1.0)])[0];
+    assert!((dot - 11.0).abs() < 0.0001);
+}
+
+#[test]
+fn test_jacobian_reverse_mode() {
+    // more inputs than outputs
+    let x = Value::new(1.0);
+    let y = Value::new(2.0);
+    let z = Value::new(3.0);
+    let f1 = &x * &y * &z;
+    let f2 = x.sin() + &y;
+    z.set_grad(5.0);
+    let jac = jacobian(&[&f1, &f2], &[&x, &y, &z]);
+    let expected = [[6.0, 3.0, 2.0], [1.0_f32.cos(), 1.0, 0.0]];
+    for (row, e) in jac.iter().zip(expected) {
+        for (a, b) in row.iter().zip(e) {
+            assert!((a - b).abs() < 0.0001, "{:?}", jac);
+        }
+    }
+    // grads that were there before the call are restored
+    assert_eq!(z.borrow_grad(), 5.0);
+    // an input the outputs don't depend on gets zeros, not its stale grad
+    let w = Value::new(4.0);
+    w.set_grad(7.0);
+    let jac = jacobian(&[&f1, &f2], &[&w, &x]);
+    assert_eq!(jac[0][0], 0.0);
+    assert_eq!(jac[1][0], 0.0);
+    assert_eq!(w.borrow_grad(), 7.0);
+    assert_eq!(x.borrow_grad(), 0.0);
+    assert_eq!(f1.borrow_grad(), 0.0);
+}
+
+#[test]
+fn test_jacobian_forward_mode() {
+    // more outputs than inputs
+    let x = Value::new(2.0);
+    let outputs = [x.pow(2), x.exp(), &x * 3.0];
+    let refs: Vec<&Value> = outputs.iter().collect();
+    let jac = jacobian(&refs, &[&x]);
+    assert_eq!(jac.len(), 3);
+    assert!((jac[0][0] - 4.0).abs() < 0.0001);
+    assert!((jac[1][0] - 2.0_f32.exp()).abs() < 0.0001);
+    assert!((jac[2][0] - 3.0).abs() < 0.0001);
+}
+
+#[test]
+fn test_jacobian_of_mlp() {
+    let m: Mlp = Mlp::new(3, &[4, 2]);
+    let x = [0.5, -1.0, 2.0].map(Value::new);
+    let y = m.forward(&x);
+    let jac = jacobian(&[&y[0], &y[1]], &x.iter().collect::<Vec<_>>());
+    // compare against finite differences
+    let h = 0.001;
+    for j in 0..3 {
+        let mut xp = [0.5, -1.0, 2.0];
+        let mut xm = xp;
+        xp[j] += h;
+        xm[j] -= h;
+        let (yp, ym) = (m.predict(&xp), m.predict(&xm));
+        for i in 0..2 {
+            let fd = (yp[i] - ym[i]) / (2.0 * h);
+            assert!((jac[i][j] - fd).abs() < 0.01, "{} vs {}", jac[i][j], fd);
+        }
+    }
+}
+
+#[test]
+fn test_hvp() {
+    // f = x^2 y + y^3, H = [[2y, 2x], [2x, 6y]]
+    let x = Value::new(1.0);
+    let y = Value::new(2.0);
+    let f = x.pow(2) * &y + y.pow(3);
+    let hv = hvp(&f, &[&x, &y], &[1.0, -1.0]);
+    assert!((hv[0] - (4.0 - 2.0)).abs() < 0.0001);
+    assert!((hv[1] - (2.0 - 12.0)).abs() < 0.0001);
+    assert_eq!(x.borrow_grad(), 0.0);
+}
+
+/// Runs `gradcheck` in `f64`, where central differences are accurate enough
+/// for a tight tolerance.
+fn check(f: impl Fn(&[super::Value<f64>]) -> super::Value<f64>, inputs: &[f64]) {
+    let inputs: Vec<super::Value<f64>> = inputs.iter().map(|&x| super::Value::new(x)).collect();
+    if let Err(e) = gradcheck(f, &inputs, 1e-6, 1e-6) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn test_gradcheck_arithmetic() {
+    check(|x| &x[0] + &x[1], &[0.3, -1.2]);
+    check(|x| x.iter().sum(), &[0.3, -1.2, 2.0, 0.1]);
+    check(|x| &x[0] * &x[1], &[0.3, -1.2]);
+    check(|x| x.iter().product(), &[0.3, -1.2, 2.0, 0.0]);
+    check(|x| &x[0] - &x[1], &[0.3, -1.2]);
+    check(|x| &x[0] / &x[1], &[0.3, -1.2]);
+    check(|x| 2.0 / &x[0] - 3.0 * &x[0], &[0.7]);
+}
+
+#[test]
+fn test_gradcheck_pow() {
+    check(|x| x[0].pow(3), &[-1.3]);
+    check(|x| x[0].pow(-2), &[0.8]);
+    check(|x| x[0].powf(&x[1]), &[1.7, 2.5]);
+    check(|x| x[0].powf(&x[1]), &[0.4, -0.5]);
+}
+
+#[test]
+fn test_gradcheck_activations() {
+    for x in [-2.5, -0.3, 0.4, 3.0] {
+        check(|v| v[0].tanh(), &[x]);
+        check(|v| v[0].sigmoid(), &[x]);
+        check(|v| v[0].softplus(), &[x]);
+        check(|v| v[0].relu(), &[x]);
+        check(|v| v[0].leaky_relu(0.2), &[x]);
+        check(|v| v[0].exp(), &[x]);
+        check(|v| v[0].abs(), &[x]);
+    }
+}
+
+#[test]
+fn test_gradcheck_logs_and_roots() {
+    check(|x| x[0].ln(), &[0.6]);
+    check(|x| x[0].log(10.0), &[3.2]);
+    check(|x| x[0].sqrt(), &[2.3]);
+}
+
+#[test]
+fn test_gradcheck_trig() {
+    for x in [-1.1, 0.2, 0.9] {
+        check(|v| v[0].sin(), &[x]);
+        check(|v| v[0].cos(), &[x]);
+        check(|v| v[0].tan(), &[x]);
+        check(|v| v[0].sinh(), &[x]);
+        check(|v| v[0].cosh(), &[x]);
+    }
+    for (y, x) in [(1.0, 2.0), (-0.5, -1.5), (0.3, -0.1)] {
+        check(|v| v[0].atan2(&v[1]), &[y, x]);
+    }
+}
+
+#[test]
+fn test_gradcheck_composite() {
+    check(
+        |x| ((&x[0] * &x[1]).tanh() + x[2].exp().ln() * x[0].sin()).pow(2),
+        &[0.5, -0.7, 1.2],
+    );
+}
+
+#[test]
+fn test_gradcheck_reports_stop_gradient() {
+    // stop_gradient deliberately disagrees with the finite difference
+    let inputs = [super::Value::new(0.5_f64), super::Value::new(2.0)];
+    let err = gradcheck(|x| x[0].stop_gradient() * &x[1], &inputs, 1e-6, 1e-6).unwrap_err();
+    assert_eq!(err.mismatches.len(), 1);
+    assert_eq!(err.mismatches[0].index, 0);
+    assert_eq!(err.mismatches[0].analytic, 0.0);
+    assert!((err.mismatches[0].numeric - 2.0).abs() < 1e-6);
+    assert!(err.to_string().contains("input 0"));
+}
+
+#[test]
+fn test_gradcheck_restores_inputs() {
+    let inputs = [Value::new(0.5), Value::new(2.0)];
+    inputs[1].set_grad(3.0);
+    gradcheck(|x| &x[0] * &x[1], &inputs, 0.001, 0.01).unwrap();
+    assert_eq!(inputs[0].borrow_data(), 0.5);
+    assert_eq!(inputs[1].borrow_data(), 2.0);
+    assert_eq!(inputs[0].borrow_grad(), 0.0);
+    assert_eq!(inputs[1].borrow_grad(), 3.0);
+}
+
+/// Squared error that stops growing (and stops passing gradient) past `cap`.
+#[derive(Debug)]
+struct ClippedSquaredError {
+    cap: f32,
+}
+
+impl CustomOp for ClippedSquaredError {
+    fn forward(&self, inputs: &[f32]) -> f32 {
+        (inputs[0] - inputs[1]).powi(2).min(self.cap)
+    }
+
+    fn backward(&self, inputs: &[f32], out: f32, grad: f32) -> Vec<f32> {
+        if out >= self.cap {
+            return vec![0.0, 0.0];
+        }
+        let d = 2.0 * (inputs[0] - inputs[1]) * grad;
+        vec![d, -d]
+    }
+}
+
+/// Piecewise-linear interpolation in a table sampled at 0, 1, 2, ...
+#[derive(Debug)]
+struct Lookup {
+    table: Vec<f64>,
+}
+
+impl Lookup {
+    fn segment(&self, x: f64) -> (usize, f64) {
+        let i = (x.floor().max(0.0) as usize).min(self.table.len() - 2);
+        (i, self.table[i + 1] - self.table[i])
+    }
+}
+
+impl CustomOp<f64> for Lookup {
+    fn forward(&self, inputs: &[f64]) -> f64 {
+        let (i, slope) = self.segment(inputs[0]);
+        self.table[i] + slope * (inputs[0] - i as f64)
+    }
+
+    fn backward(&self, inputs: &[f64], _out: f64, grad: f64) -> Vec<f64> {
+        vec![self.segment(inputs[0]).1 * grad]
+    }
+}
+
+#[test]
+fn test_custom_op_backward() {
+    let pred = Value::new(1.5);
+    let target = Value::new(1.0);
+    let loss = Value::apply(
+        ClippedSquaredError { cap: 4.0 },
+        &[pred.clone(), target.clone()],
+    );
+    assert_eq!(loss.borrow_data(), 0.25);
+    assert_eq!(loss.children(), vec![pred.clone(), target.clone()]);
+    (&loss * 3.0).backward();
+    assert_eq!(pred.borrow_grad(), 3.0);
+    assert_eq!(target.borrow_grad(), -3.0);
+
+    let pred = Value::new(5.0);
+    let loss = Value::apply(ClippedSquaredError { cap: 4.0 }, &[pred.clone(), target]);
+    loss.backward();
+    assert_eq!(loss.borrow_data(), 4.0);
+    assert_eq!(pred.borrow_grad(), 0.0);
+}
+
+#[test]
+fn test_custom_op_gradcheck() {
+    let table = vec![0.0, 1.0, 4.0, 9.0, 16.0];
+    for x in [0.3, 1.5, 2.7, 3.2] {
+        check(
+            |v| {
+                let t = Lookup {
+                    table: table.clone(),
+                };
+                super::Value::apply(t, &[v[0].sin() + 2.0]).exp()
+            },
+            &[x],
+        );
+    }
+}
+
+#[test]
+fn test_custom_op_forward_mode_and_grad_graph() {
+    let x = super::Value::new(1.25_f64);
+    let y = super::Value::apply(
+        Lookup {
+            table: vec![0.0, 1.0, 4.0],
+        },
+        &[&x * 2.0],
+    );
+    assert_eq!(y.borrow_data(), 5.5);
+    assert_eq!(jvp(&[&y], &[(&x, 1.0)]), vec![6.0]);
+    assert_eq!(y.grad_graph(&[&x])[0].borrow_data(), 6.0);
+}
+
+#[derive(Debug)]
+struct WrongArity;
+
+impl CustomOp for WrongArity {
+    fn forward(&self, inputs: &[f32]) -> f32 {
+        inputs[0]
+    }
+
+    fn backward(&self, _inputs: &[f32], _out: f32, _grad: f32) -> Vec<f32> {
+        vec![]
+    }
+}
+
+#[test]
+#[should_panic(expected = "WrongArity::backward returned 0 gradients for 1 inputs")]
+fn test_custom_op_wrong_arity() {
+    Value::apply(WrongArity, &[Value::new(1.0)]).backward();
+}
+
+#[test]
+fn test_to_dot() {
+    let a = Value::new(2.0);
+    a.set_label("a");
+    let b = (&a + &a).tanh();
+    b.set_label("b");
+    b.backward();
+    let dot = b.to_dot();
+
+    assert!(dot.starts_with("digraph {"));
+    assert!(dot.ends_with("}\n"));
+    assert_eq!(dot.matches("label=\"{ a |").count(), 1);
+    assert!(dot.contains("label=\"{ b | data 0.9993 |"));
+    assert!(dot.contains("[label=\"+\"]"));
+    assert!(dot.contains("[label=\"tanh\"]"));
+    // `a` is used twice by the sum, so it has two edges but one node.
+    assert_eq!(dot.matches(&format!("n{} -> ", a.id)).count(), 2);
+    assert_eq!(dot.matches("shape=record").count(), 3);
+}
+
+#[test]
+fn test_to_dot_escapes_labels() {
+    let a = Value::new(1.0);
+    a.set_label("{x|\"y\"}");
+    assert!(a
+        .to_dot()
+        .contains(r#"label="{ \{x\|\"y\"\} | data 1.0000"#));
+}
+
+#[test]
+fn test_detect_anomaly_forward() {
+    let x = Value::new(-1.0).with_label("x");
+    let err = detect_anomaly(|| (&x * 2.0).ln()).unwrap_err();
+    assert_eq!(err.pass, Pass::Forward);
+    assert_eq!(err.op, "ln");
+    assert_eq!(err.operands.len(), 1);
+    assert_eq!(err.operands[0].data, -2.0);
+    assert!(err.value.is_nan());
+    assert!(err
+        .to_string()
+        .starts_with("`ln` produced NaN from operands [-2]"));
+}
+
+#[test]
+fn test_detect_anomaly_backward() {
+    let x = Value::new(0.0).with_label("x");
+    let err = detect_anomaly(|| x.sqrt().with_label("root").backward()).unwrap_err();
+    assert_eq!(err.pass, Pass::Backward { operand: 0 });
+    assert_eq!(err.op, "sqrt");
+    assert_eq!(err.value, f64::INFINITY);
+    assert_eq!(err.label.as_deref(), Some("root"));
+    assert_eq!(
+        err.operands,
+        vec![Operand {
+            data: 0.0,
+            label: Some("x".to_string())
+        }]
+    );
+    assert_eq!(
+        err.to_string(),
+        "`sqrt` produced gradient inf for operand 0 at `root` from operands [0 (`x`)]"
+    );
+}
+
+#[test]
+fn test_detect_anomaly_off_by_default() {
+    let x = Value::new(-1.0);
+    assert!(x.ln().borrow_data().is_nan());
+    let y = detect_anomaly(|| (&x * 2.0).exp()).unwrap();
+    assert_eq!(y.borrow_data(), (-2.0f32).exp());
+    // the mode only lasts for the closure
+    let z = Value::new(0.0);
+    z.sqrt().backward();
+    assert_eq!(z.borrow_grad(), f32::INFINITY);
+}
+
+#[test]
+#[should_panic(expected = "unrelated")]
+fn test_detect_anomaly_passes_on_other_panics() {
+    let _ = detect_anomaly(|| panic!("unrelated"));
+}
+
+#[test]
+fn test_interrupted_backward_keeps_grads() {
+    let p = Value::new(0.5);
+    p.set_grad(5.0);
+    let y = &p.tanh() + &Value::new(0.0).sqrt();
+    let err = detect_anomaly(|| y.backward()).unwrap_err();
+    assert_eq!(err.op, "sqrt");
+    assert_eq!(p.borrow_grad(), 5.0);
+    for v in build_topo(&y).iter().filter(|v| **v != p) {
+        assert_eq!(v.borrow_grad(), 0.0);
+    }
+}
+
+#[test]
+fn test_detect_anomaly_is_silent() {
+    // other tests panic concurrently, so only count this thread's panics
+    let silenced = || crate::anomaly::SILENCED.with(|n| n.get());
+    let x = Value::new(-1.0);
+    assert!(detect_anomaly(|| x.ln()).is_err());
+    assert_eq!(silenced(), 1);
+    assert!(detect_anomaly(|| x.sqrt()).is_err());
+    assert_eq!(silenced(), 2);
+    // an anomaly `f` catches itself is still cleared on the way out
+    let caught = || std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| x.ln())).is_err();
+    assert_eq!(detect_anomaly(caught), Ok(true));
+    assert_eq!(silenced(), 3);
+    assert!(crate::anomaly::ANOMALY.with(|anomaly| anomaly.borrow().is_none()));
+}
+
+#[test]
+fn test_hook_modifies_propagated_grad() {
+    let x = Value::new(1.0);
+    let h = &x * 4.0;
+    // clip inside the graph
+    h.register_hook(|g: f32| g.clamp(-1.0, 1.0));
+    let y = &h * 5.0;
+    y.backward();
+    assert_eq!(h.borrow_grad(), 1.0);
+    assert_eq!(x.borrow_grad(), 4.0);
+}
+
+#[test]
+fn test_hooks_see_final_grad_in_order() {
+    use std::sync::{Arc, Mutex};
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let x = Value::new(3.0);
+    let log = seen.clone();
+    x.register_hook(move |g| {
+        log.lock().unwrap().push(g);
+        g * 2.0
+    });
+    x.register_hook(|g| g + 1.0);
+    // x is used three times, the hooks run once on the summed grad
+    let y = &(&x * &x) + &x;
+    y.backward();
+    assert_eq!(*seen.lock().unwrap(), vec![7.0]);
+    assert_eq!(x.borrow_grad(), 15.0);
+}
+
+#[test]
+fn test_hook_on_root_and_parameters() {
+    let m: Mlp = Mlp::new(2, &[2, 1]);
+    let x = [Value::new(0.5), Value::new(-1.0)];
+    let y = m.forward(&x).remove(0);
+    y.register_hook(|_| 0.0);
+    for p in m.parameters() {
+        p.register_hook(|g| g + 1.0);
+    }
+    y.backward();
+    assert_eq!(y.borrow_grad(), 0.0);
+    assert!(m.parameters().iter().all(|p| p.borrow_grad() == 1.0));
+}
+
+#[test]
+fn test_hook_sees_only_this_sweep() {
+    let p = Value::new(1.0);
+    p.register_hook(|g| 2.0 * g);
+    let y = &p * 1.0;
+    y.backward();
+    y.backward();
+    assert_eq!(p.borrow_grad(), 4.0);
+
+    // gradient accumulated before the hook was registered is left alone
+    let q = Value::new(1.0);
+    q.set_grad(10.0);
+    q.register_hook(|g| g.clamp(-1.0, 1.0));
+    (&q * 3.0).backward();
+    assert_eq!(q.borrow_grad(), 11.0);
+}
+
+#[test]
+fn test_jacobian_skips_hooks() {
+    let x = Value::new(1.0);
+    let y = Value::new(1.0);
+    let h = &x * 3.0 + &y * 2.0;
+    h.register_hook(|g| 10.0 * g);
+    let o1 = &h + 0.0;
+    // reverse mode, then forward mode, for the same entries
+    assert_eq!(jacobian(&[&o1], &[&x, &y]), vec![vec![3.0, 2.0]]);
+    assert_eq!(jacobian(&[&o1, &h], &[&x]), vec![vec![3.0], vec![3.0]]);
+
+    let p = Value::new(0.5);
+    p.register_hook(|g| 10.0 * g);
+    assert!(gradcheck(|v| &v[0] * 3.0, &[p], 1e-2, 1e-2).is_ok());
+}
+
+#[test]
+fn test_zero_local_derivative_blocks_infinite_grad() {
+    // each output is 0, so `ln` sends back an infinite gradient that the
+    // blocked or unselected operand must not see as 0 * inf
+    let x = Value::new(0.0);
+    let y = x.stop_gradient().ln();
+    y.backward();
+    assert_eq!(x.borrow_grad(), 0.0);
+    assert_eq!(jvp(&[&y], &[(&x, f32::INFINITY)]), vec![0.0]);
+
+    let x = Value::new(-1.0);
+    x.relu().ln().backward();
+    assert_eq!(x.borrow_grad(), 0.0);
+
+    let x = Value::new(-3.0);
+    x.clamp(0.0, 1.0).ln().backward();
+    assert_eq!(x.borrow_grad(), 0.0);
+
+    let (a, b) = (Value::new(0.0), Value::new(-1.0));
+    a.max(&b).ln().backward();
+    assert_eq!(a.borrow_grad(), f32::INFINITY);
+    assert_eq!(b.borrow_grad(), 0.0);
+
+    let (a, b) = (Value::new(0.0), Value::new(1.0));
+    a.min(&b).ln().backward();
+    assert_eq!(b.borrow_grad(), 0.0);
+
+    let (a, b) = (Value::new(0.0), Value::new(5.0));
+    Value::where_(true, &a, &b).ln().backward();
+    assert_eq!(b.borrow_grad(), 0.0);
+}
+
+#[test]
+fn test_backward_with_seed() {
+    let x = Value::new(3.0);
+    let y = &x * &x;
+    y.backward_with(0.5, true);
+    assert_eq!(y.borrow_grad(), 0.5);
+    assert_eq!(x.borrow_grad(), 3.0);
+    // a retained graph can be swept again, accumulating into the leaves
+    y.backward_with(1.0, true);
+    assert_eq!(x.borrow_grad(), 9.0);
+}
+
+#[test]
+fn test_repeated_backward_only_accumulates_leaves() {
+    let x = Value::new(1.0);
+    let z = &x * 2.0;
+    let y = &z * 5.0;
+    y.backward();
+    y.backward();
+    assert_eq!(x.borrow_grad(), 20.0);
+    assert_eq!(z.borrow_grad(), 5.0);
+    assert_eq!(y.borrow_grad(), 1.0);
+
+    // a leaf root accumulates its seed like any other leaf
+    x.backward_with(2.0, true);
+    assert_eq!(x.borrow_grad(), 22.0);
+}
+
+#[test]
+fn test_backward_releases_graph() {
+    let x = Value::new(2.0);
+    let h = (&x * 3.0).tanh();
+    let hidden = Shared::downgrade(&h.children()[0].0);
+    let y = &h + 1.0;
+    drop(h);
+    y.backward_with(1.0, false);
+    assert!(x.borrow_grad() > 0.0);
+    assert!(y.children().is_empty());
+    assert!(hidden.upgrade().is_none());
+    // leaves keep their data and grad
+    assert_eq!(x.borrow_data(), 2.0);
+}
+
+#[test]
+#[should_panic(expected = "backward through a graph that has already been released")]
+fn test_backward_through_released_graph() {
+    let x = Value::new(2.0);
+    let y = x.exp();
+    y.backward_with(1.0, false);
+    y.backward();
+}
+
+#[test]
+#[should_panic(expected = "backward through a graph that has already been released")]
+fn test_jvp_through_released_graph() {
+    let x = Value::new(2.0);
+    let y = x.exp();
+    y.backward_with(1.0, false);
+    jvp(&[&y], &[(&x, 1.0)]);
+}
+
+#[test]
+fn test_million_node_chain() {
+    let x = Value::new(1.0);
+    let mut y = x.clone();
+    for _ in 0..1_000_000 {
+        y = &y + &x;
+    }
+    let tape = build_topo(&y);
+    assert_eq!(tape.len(), 1_000_001);
+    assert_eq!(tape[0], x);
+    drop(tape);
+    y.backward();
+    assert_eq!(x.borrow_grad(), 1_000_001.0);
+    // dropping the last handle frees the whole chain without recursing
+    drop(y);
+}
+
+#[test]
+fn test_long_binary_sum() {
+    let xs: Vec<Value> = (0..100_000).map(|i| Value::new(i as f32)).collect();
+    let loss = xs.iter().fold(Value::new(0.0), |acc, x| &acc + x);
+    assert_eq!(build_topo(&loss).len(), 200_001);
+    loss.backward();
+    assert!(xs.iter().all(|x| x.borrow_grad() == 1.0));
+    drop(loss);
+    assert!(xs.iter().all(|x| Shared::strong_count(&x.0) == 1));
+}
+
+#[test]
+// the hash is the node id, which never changes, so the interior mutability
+// clippy warns about cannot move a key
+#[allow(clippy::mutable_key_type)]
+fn test_identity_eq_and_hash() {
+    use std::collections::HashSet;
+
+    let a = Value::new(1.0);
+    let b = Value::new(1.0);
+    assert_ne!(a, b);
+    assert_eq!(a, a.clone());
+    // different graphs computing the same number are still different nodes
+    assert_ne!(&a + &b, &a + &b);
+
+    let c = &a * &b;
+    let set: HashSet<Value> = [a.clone(), b.clone(), a.clone(), c.clone()]
+        .into_iter()
+        .collect();
+    assert_eq!(set.len(), 3);
+    assert!(set.contains(&c));
+    assert!(!set.contains(&Value::new(1.0)));
+}
+
+#[test]
+fn test_approx_eq() {
+    let a = Value::new(0.1);
+    let b = &Value::new(0.3) - &Value::new(0.2);
+    assert!(a.approx_eq(&b, 1e-6));
+    assert!(!a.approx_eq(&Value::new(0.2), 1e-6));
+    assert!(a.approx_eq(&a, 0.0));
+}
+
+#[test]
+fn test_max_min() {
+    let a = Value::new(2.0);
+    let b = Value::new(-1.0);
+    let m = a.max(&b);
+    assert_eq!(m.op, Some(Operation::Max));
+    assert_eq!(m.borrow_data(), 2.0);
+    m.backward();
+    assert_eq!((a.borrow_grad(), b.borrow_grad()), (1.0, 0.0));
+
+    let a = Value::new(2.0);
+    let b = Value::new(-1.0);
+    let m = a.min(&b);
+    assert_eq!(m.borrow_data(), -1.0);
+    m.backward();
+    assert_eq!((a.borrow_grad(), b.borrow_grad()), (0.0, 1.0));
+    assert_jvp_matches_backward(&m, &[&a, &b]);
+}
+
+#[test]
+fn test_max_min_ties_split_grad() {
+    for f in [Value::max, Value::min] {
+        let a = Value::new(0.5);
+        let b = Value::new(0.5);
+        let m = f(&a, &b);
+        m.backward();
+        assert_eq!((a.borrow_grad(), b.borrow_grad()), (0.5, 0.5));
+        assert_jvp_matches_backward(&m, &[&a, &b]);
+        let g = m.grad_graph(&[&a, &b]);
+        assert_eq!((g[0].borrow_data(), g[1].borrow_data()), (0.5, 0.5));
+    }
+    // a value maxed with itself still gets the whole gradient
+    let a = Value::new(3.0);
+    a.max(&a).backward();
+    assert_eq!(a.borrow_grad(), 1.0);
+}
+
+#[test]
+fn test_clamp() {
+    for (x, out, grad) in [
+        (-2.0, -1.0, 0.0),
+        (-1.0, -1.0, 1.0),
+        (0.3, 0.3, 1.0),
+        (1.0, 1.0, 1.0),
+        (4.0, 1.0, 0.0),
+    ] {
+        let x = Value::new(x);
+        let y = x.clamp(-1.0, 1.0);
+        assert_eq!(y.borrow_data(), out);
+        y.backward();
+        assert_eq!(x.borrow_grad(), grad);
+        assert_eq!(y.grad_graph(&[&x])[0].borrow_data(), grad);
+    }
+}
+
+#[test]
+fn test_selection_propagates_nan() {
+    let nan = Value::new(f32::NAN);
+    let zero = Value::new(0.0);
+    assert!(nan.max(&zero).borrow_data().is_nan());
+    assert!(zero.max(&nan).borrow_data().is_nan());
+    assert!(nan.min(&zero).borrow_data().is_nan());
+    assert!(zero.min(&nan).borrow_data().is_nan());
+    assert!(nan.clamp(-1.0, 1.0).borrow_data().is_nan());
+
+    // so anomaly mode still sees the divergence
+    let err = detect_anomaly(|| nan.clamp(-1.0, 1.0)).unwrap_err();
+    assert_eq!(err.op, "clamp(-1, 1)");
+
+    // and neither operand gets a finite gradient for a NaN result
+    for y in [nan.max(&zero), zero.min(&nan)] {
+        y.backward();
+        assert!(nan.borrow_grad().is_nan());
+        assert!(zero.borrow_grad().is_nan());
+        let grads = y.grad_graph(&[&nan, &zero]);
+        assert!(grads.iter().all(|g| g.borrow_data().is_nan()));
+        nan.set_grad(0.0);
+        zero.set_grad(0.0);
+    }
+}
+
+#[test]
+#[should_panic(expected = "clamp with lo > hi")]
+fn test_clamp_empty_range() {
+    Value::new(0.0).clamp(1.0, -1.0);
+}
+
+#[test]
+fn test_where() {
+    let a = Value::new(2.0);
+    let b = Value::new(5.0);
+    let y = Value::where_(a.borrow_data() > b.borrow_data(), &a, &b);
+    assert_eq!(y.borrow_data(), 5.0);
+    assert_eq!(y.children(), vec![a.clone(), b.clone()]);
+    (&y * 3.0).backward();
+    assert_eq!((a.borrow_grad(), b.borrow_grad()), (0.0, 3.0));
+
+    let y = Value::where_(true, &a, &b);
+    assert_eq!(y.borrow_data(), 2.0);
+    assert_eq!(jvp(&[&y], &[(&a, 1.0), (&b, 1.0)]), vec![1.0]);
+    assert!(y.to_dot().contains("[label=\"where(true)\"]"));
+}
+
+#[test]
+fn test_gradcheck_selection() {
+    for (a, b) in [(0.3, -1.2), (-0.4, 0.9), (0.7, 0.7)] {
+        check(|x| x[0].max(&x[1]), &[a, b]);
+        check(|x| x[0].min(&x[1]), &[a, b]);
+    }
+    for x in [-2.0, -0.3, 0.4, 3.0] {
+        check(|v| v[0].clamp(-1.0, 1.0), &[x]);
+    }
+    check(|x| super::Value::where_(true, &x[0], &x[1]), &[0.3, -1.2]);
+    check(|x| super::Value::where_(false, &x[0], &x[1]), &[0.3, -1.2]);
+    // hinge loss max(0, 1 - y * f(x))
+    check(
+        |x| (1.0 - &x[0] * (&x[1] * &x[2])).max(&super::Value::new(0.0)),
+        &[1.0, 0.4, 0.5],
+    );
+}